@@ -0,0 +1,562 @@
+use crate::hooks::run_in_background;
+use crate::log::{error, warn};
+use crate::period::start_of_day;
+use crate::procfs::{Process, ProcessId};
+use crate::report::{format_duration, played_since};
+use crate::tracker::Tracker;
+use chrono::{DateTime, Datelike, Days, Local, Timelike};
+use rusqlite::Connection;
+use serde::{Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
+
+/// How often budgets are checked while a game they cover is running.
+const CHECK_SECS: i64 = 5;
+
+/// Seconds the `command` action's command may run.
+const COMMAND_TIMEOUT: u64 = 30;
+
+/// A limit on play time, for some games or for all of them together.
+///
+/// ```yaml
+/// budgets:
+///     - games: [minecraft, roblox]
+///       daily: 2h
+///       weekly: 10h
+///       windows: ["mon-fri 16:00-20:00", "sat,sun 09:00-21:00"]
+///       action: kill
+///       grace: 300
+/// ```
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Budget {
+    /// Game ids; all games together when empty
+    #[serde(default)]
+    pub games: Vec<String>,
+    /// Seconds, or a duration like `1h30m`, per day from midnight
+    #[serde(default, deserialize_with = "duration")]
+    pub daily: Option<i64>,
+    /// Per week from Monday
+    #[serde(default, deserialize_with = "duration")]
+    pub weekly: Option<i64>,
+    /// When playing is allowed at all; any time if empty
+    #[serde(default)]
+    pub windows: Vec<Window>,
+    #[serde(default)]
+    pub action: Action,
+    /// Run with `sh -c` by the `command` action
+    pub command: Option<String>,
+    /// Seconds from going over budget to SIGTERM
+    #[serde(default = "default_grace")]
+    pub grace: u64,
+    /// Seconds from SIGTERM to SIGKILL
+    #[serde(default = "default_kill_grace")]
+    pub kill_grace: u64,
+}
+
+fn default_grace() -> u64 {
+    300
+}
+
+fn default_kill_grace() -> u64 {
+    30
+}
+
+/// What happens once a budget is used up. Every action is logged.
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    #[default]
+    Log,
+    /// Run the budget's `command`
+    Command,
+    /// SIGTERM the game's processes after `grace`
+    Terminate,
+    /// As `terminate`, then SIGKILL whatever is left after `kill_grace`
+    Kill,
+}
+
+/// Days of the week and a time of day, e.g. `mon-fri 16:00-20:00`,
+/// `sat,sun 09:00-24:00` or just `16:00-20:00` for every day.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Window {
+    /// Monday first
+    days: [bool; 7],
+    /// minutes since midnight, `start..end`
+    start: u32,
+    end: u32,
+}
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl TryFrom<String> for Window {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid time window {:?}", s);
+        let (days, times) = match s.split_whitespace().collect::<Vec<_>>()[..] {
+            [times] => ([true; 7], times),
+            [days, times] => (parse_days(days).ok_or_else(invalid)?, times),
+            _ => return Err(invalid()),
+        };
+        let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+        let (start, end) = (
+            parse_minutes(start).ok_or_else(invalid)?,
+            parse_minutes(end).ok_or_else(invalid)?,
+        );
+        if start >= end {
+            return Err(format!("time window {:?} ends before it starts", s));
+        }
+        Ok(Window { days, start, end })
+    }
+}
+
+impl Window {
+    /// Seconds until the window closes, if `time` is inside it.
+    pub fn left(&self, time: DateTime<Local>) -> Option<i64> {
+        let minutes = time.hour() * 60 + time.minute();
+        let inside = self.days[time.weekday().num_days_from_monday() as usize]
+            && (self.start..self.end).contains(&minutes);
+        inside.then(|| i64::from(self.end) * 60 - i64::from(time.num_seconds_from_midnight()))
+    }
+}
+
+/// `mon-fri`, `sat,sun`, `fri-mon`, `weekdays`, `weekends`
+fn parse_days(s: &str) -> Option<[bool; 7]> {
+    let index = |day: &str| DAYS.iter().position(|name| *name == day);
+    let mut days = [false; 7];
+    for part in s.to_lowercase().split(',') {
+        let (first, last) = match part {
+            "weekdays" => (0, 4),
+            "weekends" => (5, 6),
+            _ => match part.split_once('-') {
+                Some((first, last)) => (index(first)?, index(last)?),
+                None => (index(part)?, index(part)?),
+            },
+        };
+        let mut day = first;
+        loop {
+            days[day] = true;
+            if day == last {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Some(days)
+}
+
+/// `HH:MM`, up to `24:00`
+fn parse_minutes(s: &str) -> Option<u32> {
+    let (hours, minutes) = s.split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    let total = hours * 60 + minutes;
+    (minutes < 60 && total <= 24 * 60).then_some(total)
+}
+
+/// Plain seconds, or hours, minutes and seconds like `1h30m` or `45m`.
+fn parse_duration(s: &str) -> Result<i64, String> {
+    let invalid = || format!("invalid duration {:?}", s);
+    if let Ok(secs) = s.parse::<u32>() {
+        return Ok(secs.into());
+    }
+    let mut total = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        total += number.parse::<i64>().map_err(|_| invalid())? * unit;
+        number.clear();
+    }
+    if s.is_empty() || !number.is_empty() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+/// Deserializes seconds, or a duration string for `parse_duration`.
+pub fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Limit {
+        Seconds(u32),
+        Text(String),
+    }
+    match Limit::deserialize(deserializer)? {
+        Limit::Seconds(secs) => Ok(secs.into()),
+        Limit::Text(text) => parse_duration(&text).map_err(serde::de::Error::custom),
+    }
+}
+
+/// As `seconds`, for fields that may be left out.
+pub fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    seconds(deserializer).map(Some)
+}
+
+impl Budget {
+    pub fn describe(&self) -> String {
+        if self.games.is_empty() {
+            "all games".to_string()
+        } else {
+            self.games.join(", ")
+        }
+    }
+
+    /// How long the budget's games may still be played from `now`.
+    fn remaining(
+        &self,
+        conn: &Connection,
+        now: DateTime<Local>,
+    ) -> crate::error::Result<Allowance> {
+        let mut left = i64::MAX;
+        if !self.windows.is_empty() {
+            match self
+                .windows
+                .iter()
+                .filter_map(|window| window.left(now))
+                .max()
+            {
+                Some(until_closed) => left = until_closed,
+                None => return Ok(Allowance::UsedUp("outside the allowed times".to_string())),
+            }
+        }
+        let today = now.date_naive();
+        let monday = today - Days::new(today.weekday().num_days_from_monday().into());
+        let limits: Vec<(i64, DateTime<Local>, &str)> = [
+            (self.daily, start_of_day(today), "today"),
+            (self.weekly, start_of_day(monday), "this week"),
+        ]
+        .into_iter()
+        .filter_map(|(limit, since, period)| Some((limit?, since, period)))
+        .collect();
+        if limits.is_empty() {
+            return Ok(Allowance::Left(left));
+        }
+        let since: Vec<DateTime<Local>> = limits.iter().map(|(_, since, _)| *since).collect();
+        let played = played_since(conn, &self.games, &since, now)?;
+        for ((limit, _, period), played) in limits.into_iter().zip(played) {
+            if played >= limit {
+                return Ok(Allowance::UsedUp(format!(
+                    "{} played {}, {} allowed",
+                    format_duration(played),
+                    period,
+                    format_duration(limit)
+                )));
+            }
+            left = left.min(limit - played);
+        }
+        Ok(Allowance::Left(left))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Allowance {
+    /// Seconds
+    Left(i64),
+    /// Why there is no time left
+    UsedUp(String),
+}
+
+#[derive(Debug, PartialEq)]
+enum Step {
+    /// Log, and run the command if there is one
+    Notice,
+    Terminate(Vec<ProcessId>),
+    Kill(Vec<ProcessId>),
+}
+
+/// A budget found used up while its games were running.
+#[derive(Default)]
+struct Over {
+    noticed: bool,
+    /// When the grace period of the processes not signalled yet began;
+    /// `None` while there are none
+    grace_from: Option<DateTime<Local>>,
+    /// Processes sent SIGTERM, and when
+    terminated: HashMap<ProcessId, DateTime<Local>>,
+    /// Processes sent SIGKILL
+    killed: HashSet<ProcessId>,
+}
+
+impl Over {
+    /// What is due at `now` for the budget's running `procs`, in order.
+    fn steps(&mut self, budget: &Budget, procs: &[ProcessId], now: DateTime<Local>) -> Vec<Step> {
+        let mut steps = Vec::new();
+        if !self.noticed {
+            self.noticed = true;
+            steps.push(Step::Notice);
+        }
+        if !matches!(budget.action, Action::Terminate | Action::Kill) {
+            return steps;
+        }
+        self.terminated.retain(|id, _| procs.contains(id));
+        self.killed.retain(|id| procs.contains(id));
+
+        // a game started again after SIGTERM gets a grace period of its own
+        let fresh: Vec<ProcessId> = procs
+            .iter()
+            .filter(|id| !self.terminated.contains_key(id))
+            .copied()
+            .collect();
+        if fresh.is_empty() {
+            self.grace_from = None;
+        } else if (now - *self.grace_from.get_or_insert(now)).num_seconds() >= budget.grace as i64 {
+            self.grace_from = None;
+            self.terminated.extend(fresh.iter().map(|id| (*id, now)));
+            steps.push(Step::Terminate(fresh));
+        }
+
+        if budget.action == Action::Kill {
+            let due: Vec<ProcessId> = self
+                .terminated
+                .iter()
+                .filter(|(id, terminated)| {
+                    !self.killed.contains(id)
+                        && (now - **terminated).num_seconds() >= budget.kill_grace as i64
+                })
+                .map(|(id, _)| *id)
+                .collect();
+            if !due.is_empty() {
+                self.killed.extend(&due);
+                steps.push(Step::Kill(due));
+            }
+        }
+        steps
+    }
+}
+
+/// Checks budgets against the recorded sessions and carries out their
+/// actions. A budget stays used up until its limits allow play again;
+/// each process gets one grace period, so one started meanwhile is
+/// stopped too, but not before its own grace is over.
+#[derive(Default)]
+pub struct Enforcer {
+    /// budget index -> what was done about it since it got used up
+    over: HashMap<usize, Over>,
+    last_check: Option<DateTime<Local>>,
+}
+
+impl Enforcer {
+    /// Returns the seconds left of each budget that was checked, by index;
+    /// those are the ones whose games are running.
+    pub fn check(
+        &mut self,
+        conn: &Connection,
+        budgets: &[Budget],
+        tracker: &Tracker,
+        now: DateTime<Local>,
+    ) -> Vec<(usize, i64)> {
+        let mut remaining = Vec::new();
+        if self
+            .last_check
+            .is_some_and(|last| (now - last).num_seconds() < CHECK_SECS)
+        {
+            return remaining;
+        }
+        self.last_check = Some(now);
+
+        for (i, budget) in budgets.iter().enumerate() {
+            let procs = tracker.processes(&budget.games);
+            if procs.is_empty() {
+                continue;
+            }
+            let reason = match budget.remaining(conn, now) {
+                Ok(Allowance::Left(left)) => {
+                    remaining.push((i, left));
+                    self.over.remove(&i);
+                    continue;
+                }
+                Ok(Allowance::UsedUp(reason)) => {
+                    remaining.push((i, 0));
+                    reason
+                }
+                // tried again at the next check
+                Err(e) => {
+                    error!("Cannot check budget for {}: {}", budget.describe(), e);
+                    continue;
+                }
+            };
+            let over = self.over.entry(i).or_default();
+            for step in over.steps(budget, &procs, now) {
+                match step {
+                    Step::Notice => {
+                        warn!("Budget for {} used up: {}", budget.describe(), reason);
+                        if let (Action::Command, Some(command)) = (budget.action, &budget.command) {
+                            let env = vec![
+                                ("PLAYTIME_GAMES", budget.games.join(",")),
+                                ("PLAYTIME_REASON", reason.clone()),
+                            ];
+                            run_in_background(command, env, COMMAND_TIMEOUT);
+                        }
+                        if matches!(budget.action, Action::Terminate | Action::Kill) {
+                            warn!("Stopping them in {} seconds", budget.grace);
+                        }
+                    }
+                    Step::Terminate(ids) => {
+                        warn!("Sending SIGTERM to {}", budget.describe());
+                        signal(&ids, libc::SIGTERM);
+                    }
+                    Step::Kill(ids) => {
+                        warn!("Sending SIGKILL to {}", budget.describe());
+                        signal(&ids, libc::SIGKILL);
+                    }
+                }
+            }
+        }
+        remaining
+    }
+
+    /// Forgets what was used up, for when the budgets changed.
+    pub fn reset(&mut self) {
+        self.over.clear();
+        self.last_check = None;
+    }
+}
+
+/// Signals the processes that are still the ones we tracked, so a reused
+/// pid is left alone.
+fn signal(procs: &[ProcessId], signal: i32) {
+    for id in procs {
+        if Process::read(id.pid).is_some_and(|proc| proc.id() == *id) {
+            // SAFETY: plain syscall on a pid we just checked
+            unsafe { libc::kill(id.pid, signal) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::open_in_memory;
+    use chrono::TimeDelta;
+
+    fn budget(yaml: &str) -> Budget {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_window() {
+        let window = Window::try_from("mon-fri 16:00-20:00".to_string()).unwrap();
+        // 2025-06-02 is a Monday
+        let at = |day: u32, hm: &str| {
+            crate::period::parse_time(&format!("2025-06-{:02} {}", day, hm)).unwrap()
+        };
+        assert_eq!(window.left(at(2, "16:00")), Some(4 * 3600));
+        assert_eq!(window.left(at(2, "20:00")), None);
+        assert_eq!(window.left(at(7, "17:00")), None);
+
+        let window = Window::try_from("fri-sun,wed 00:00-24:00".to_string()).unwrap();
+        assert_eq!(window.days, [false, false, true, false, true, true, true]);
+        assert_eq!(window.left(at(8, "23:59")), Some(60));
+        assert!(Window::try_from("20:00-16:00".to_string()).is_err());
+        assert!(Window::try_from("someday 10:00-11:00".to_string()).is_err());
+        assert!(Window::try_from("10:00-25:00".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_budget_config() {
+        let parsed = budget("{games: [factorio], daily: 1h30m, weekly: 36000, action: kill}");
+        assert_eq!(parsed.daily, Some(5400));
+        assert_eq!(parsed.weekly, Some(36000));
+        assert_eq!((parsed.grace, parsed.kill_grace), (300, 30));
+        assert_eq!(budget("daily: 45m").action, Action::Log);
+        assert!(serde_yaml::from_str::<Budget>("daily: 2 hours").is_err());
+        assert!(serde_yaml::from_str::<Budget>("action: shout").is_err());
+    }
+
+    #[test]
+    fn test_exceeded() {
+        let conn = open_in_memory();
+        let now = Local::now();
+        // well inside today, whenever the test runs
+        let start = start_of_day(now.date_naive()).max(now - TimeDelta::minutes(30));
+        conn.execute(
+            "INSERT INTO sessions (path, pid, started, ended, game)
+            VALUES ('factorio', 1, ?1, ?2, 'factorio')",
+            [start.to_rfc3339(), now.to_rfc3339()],
+        )
+        .unwrap();
+        let played = (now - start).num_seconds();
+        let limit = |secs: i64| budget(&format!("{{games: [factorio], daily: {}}}", secs));
+        let remaining = |budget: Budget| budget.remaining(&conn, now).unwrap();
+        assert_eq!(remaining(limit(played + 60)), Allowance::Left(60));
+        assert!(matches!(remaining(limit(played)), Allowance::UsedUp(_)));
+        // other games do not count
+        let other = budget("{games: [tetris], daily: 1}");
+        assert_eq!(remaining(other), Allowance::Left(1));
+    }
+
+    #[test]
+    fn test_steps() {
+        let budget = budget("{daily: 1h, action: kill, grace: 60, kill_grace: 10}");
+        let start = Local::now();
+        let at = |secs: i64| start + TimeDelta::seconds(secs);
+        let game = [ProcessId {
+            pid: 10,
+            starttime: 0,
+        }];
+        let mut over = Over::default();
+        assert_eq!(over.steps(&budget, &game, at(0)), [Step::Notice]);
+        assert!(over.steps(&budget, &game, at(30)).is_empty());
+        assert_eq!(
+            over.steps(&budget, &game, at(60)),
+            [Step::Terminate(game.to_vec())]
+        );
+        assert!(over.steps(&budget, &game, at(65)).is_empty());
+        assert_eq!(
+            over.steps(&budget, &game, at(70)),
+            [Step::Kill(game.to_vec())]
+        );
+        // once is enough
+        assert!(over.steps(&budget, &game, at(75)).is_empty());
+    }
+
+    #[test]
+    fn test_steps_after_relaunch() {
+        let start = Local::now();
+        let at = |secs: i64| start + TimeDelta::seconds(secs);
+        let first = [ProcessId {
+            pid: 10,
+            starttime: 0,
+        }];
+        let again = [ProcessId {
+            pid: 11,
+            starttime: 8000,
+        }];
+
+        let kill = budget("{daily: 1h, action: kill, grace: 60, kill_grace: 10}");
+        let mut over = Over::default();
+        over.steps(&kill, &first, at(0));
+        over.steps(&kill, &first, at(60));
+        over.steps(&kill, &first, at(70));
+        // the relaunched game gets SIGTERM and a grace period, not SIGKILL
+        assert!(over.steps(&kill, &again, at(80)).is_empty());
+        assert!(over.steps(&kill, &again, at(130)).is_empty());
+        assert_eq!(
+            over.steps(&kill, &again, at(140)),
+            [Step::Terminate(again.to_vec())]
+        );
+        assert_eq!(
+            over.steps(&kill, &again, at(150)),
+            [Step::Kill(again.to_vec())]
+        );
+
+        let terminate = budget("{daily: 1h, action: terminate, grace: 60}");
+        let mut over = Over::default();
+        over.steps(&terminate, &first, at(0));
+        over.steps(&terminate, &first, at(60));
+        assert!(over.steps(&terminate, &again, at(80)).is_empty());
+        assert_eq!(
+            over.steps(&terminate, &again, at(140)),
+            [Step::Terminate(again.to_vec())]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use crate::period::{Interval, Period, parse_period, parse_time};
+use chrono::{DateTime, Local};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+/// Tracks how long games and other programs run.
+#[derive(Parser)]
+#[command(
+    version,
+    after_help = "Exit status: 0 on success, 65 for invalid input, 69 when the daemon is not \
+                  running or refuses, 74 for database and I/O errors, 78 for config errors."
+)]
+pub struct Cli {
+    /// Config file [default: ~/.config/playtime-tracker/config.yaml]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Session database [default: ~/.local/share/playtime-tracker.sqlite]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub db: Option<PathBuf>,
+
+    /// Control socket of the daemon [default: $XDG_RUNTIME_DIR/playtime-tracker.sock]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub socket: Option<PathBuf>,
+
+    /// Runs the daemon when omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Watch for tracked programs and record sessions
+    Daemon,
+    /// Total playtime per game
+    Report {
+        #[command(flatten)]
+        filter: Filter,
+        /// Order of the games
+        #[arg(long, value_enum, default_value_t = Sort::Name)]
+        sort: Sort,
+        /// Show a table of time per day, week or month instead of totals
+        #[arg(long, value_enum, value_name = "INTERVAL")]
+        by: Option<Interval>,
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+    /// List recorded sessions
+    Sessions {
+        #[command(flatten)]
+        filter: Filter,
+        /// Only the most recent N sessions
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+    /// Show what is being tracked right now and for how long
+    Status,
+    /// Make the running daemon re-read its config
+    Reload,
+    /// Stop counting play time until resumed
+    Pause {
+        /// Only this game, by id or display name
+        game: Option<String>,
+    },
+    /// Start counting play time again after a pause
+    Resume {
+        /// Only this game, by id or display name
+        game: Option<String>,
+    },
+    /// Make the running daemon write its state to the database now
+    Flush,
+    /// End open sessions and stop the running daemon
+    Stop,
+    /// Correct recorded sessions by hand
+    Session {
+        #[command(subcommand)]
+        command: SessionCommand,
+    },
+    /// Inspect the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Inspect or back up the session database
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SessionCommand {
+    /// Record a session the daemon did not see, such as one played on a
+    /// console
+    Add {
+        /// By id or display name
+        game: String,
+        /// YYYY-MM-DD HH:MM or RFC 3339
+        #[arg(long, value_parser = parse_time)]
+        start: DateTime<Local>,
+        #[arg(long, value_parser = parse_time)]
+        end: DateTime<Local>,
+    },
+    /// Change the game or times of a finished session
+    Edit {
+        /// As listed by `sessions`
+        id: i64,
+        #[arg(long)]
+        game: Option<String>,
+        #[arg(long, value_parser = parse_time)]
+        start: Option<DateTime<Local>>,
+        #[arg(long, value_parser = parse_time)]
+        end: Option<DateTime<Local>>,
+    },
+    /// Remove a finished session
+    Delete { id: i64 },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the config file location
+    Path,
+    /// Parse the config and list the games it tracks
+    Check,
+}
+
+#[derive(Subcommand)]
+pub enum DbCommand {
+    /// Print the database location
+    Path,
+    /// Show the schema version and what the database holds
+    Info,
+    /// Write a consistent copy of the database to FILE
+    Backup { file: PathBuf },
+}
+
+/// Output format of `report` and `sessions`.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// For people
+    Table,
+    /// An array of objects
+    Json,
+    /// With a header row
+    Csv,
+}
+
+/// Sessions straddling the edges of the selected time are cut to the part
+/// inside it.
+#[derive(Args, Default)]
+pub struct Filter {
+    /// today, yesterday, this-week, last-week, this-month, last-month,
+    /// this-year, last-year, 2025-06-01, 2025-06, 2025 or FROM..TO
+    #[arg(value_parser = parse_period)]
+    pub period: Option<Period>,
+    /// Only time at or after this (YYYY-MM-DD, YYYY-MM-DD HH:MM or RFC 3339)
+    #[arg(long, value_parser = parse_time)]
+    pub since: Option<DateTime<Local>>,
+    /// Only time before this
+    #[arg(long, value_parser = parse_time)]
+    pub until: Option<DateTime<Local>>,
+    /// Only this game, by id or display name (repeatable)
+    #[arg(long = "game", value_name = "GAME")]
+    pub games: Vec<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Sort {
+    /// By display name
+    Name,
+    /// Most played first
+    Time,
+    /// Most sessions first
+    Sessions,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from([
+            "playtime",
+            "report",
+            "--since",
+            "2025-06-01",
+            "--game",
+            "factorio",
+            "--sort",
+            "time",
+            "--db",
+            "/tmp/x.sqlite",
+        ])
+        .unwrap();
+        assert_eq!(cli.db, Some(PathBuf::from("/tmp/x.sqlite")));
+        let Some(Command::Report { filter, sort, .. }) = cli.command else {
+            panic!("expected report");
+        };
+        assert!(matches!(sort, Sort::Time));
+        assert_eq!(filter.games, ["factorio"]);
+        assert!(filter.since.is_some() && filter.until.is_none());
+
+        assert!(Cli::try_parse_from(["playtime"]).unwrap().command.is_none());
+        assert!(Cli::try_parse_from(["playtime", "report", "--since", "June"]).is_err());
+        let cli = Cli::try_parse_from(["playtime", "sessions", "last-week"]).unwrap();
+        let Some(Command::Sessions { filter, .. }) = cli.command else {
+            panic!("expected sessions");
+        };
+        assert!(filter.period.is_some());
+    }
+}
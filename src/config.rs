@@ -0,0 +1,382 @@
+use crate::budget::{Action, Budget};
+use crate::error::Error;
+use crate::hooks::{Hook, HookEvent};
+use crate::idle::IdleConfig;
+use crate::log::{LogConfig, info};
+use crate::matcher::Matcher;
+use crate::notify::NotifyConfig;
+use crate::procfs::Process;
+use crate::source::SourceKind;
+use crate::tracker::HEARTBEAT_SECS;
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Deserialize)]
+#[serde(try_from = "RawConfig")]
+pub struct Config {
+    pub games: Vec<Game>,
+    /// `auto`, `netlink` or `poll`; see `source::open`
+    pub process_source: SourceKind,
+    pub poll: Poll,
+    pub idle: Option<IdleConfig>,
+    pub budgets: Vec<Budget>,
+    pub hooks: Vec<Hook>,
+    pub notifications: Option<NotifyConfig>,
+    pub log: LogConfig,
+}
+
+/// How often the daemon scans for processes and records heartbeats.
+///
+/// ```yaml
+/// poll:
+///     interval: 2
+///     adaptive: true
+///     idle_interval: 20
+/// ```
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Poll {
+    /// Seconds between scans
+    pub interval: f64,
+    /// Scan every `idle_interval` seconds while nothing tracked is running,
+    /// and every `interval` from the moment a game starts until it ends
+    pub adaptive: bool,
+    pub idle_interval: f64,
+}
+
+impl Default for Poll {
+    fn default() -> Self {
+        Poll {
+            interval: 1.0,
+            adaptive: false,
+            idle_interval: 10.0,
+        }
+    }
+}
+
+/// A tracked game. `id` is what sessions are stored against, so it should
+/// stay the same even if the binary or launcher changes.
+///
+/// ```yaml
+/// games:
+///     - id: hollow_knight
+///       name: Hollow Knight
+///       match:
+///           - "hollow_knight.x86_64"
+///           - cmdline: "HollowKnight\\.exe"
+/// ```
+#[derive(Deserialize)]
+pub struct Game {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "match")]
+    pub matchers: Vec<Matcher>,
+}
+
+#[derive(Deserialize)]
+struct RawConfig {
+    #[serde(default)]
+    games: Vec<Game>,
+    /// Older configs list bare rules; each one becomes a game of its own.
+    #[serde(default)]
+    tracked: Vec<Matcher>,
+    #[serde(default)]
+    process_source: SourceKind,
+    #[serde(default)]
+    poll: Poll,
+    #[serde(default)]
+    idle: Option<IdleConfig>,
+    #[serde(default)]
+    budgets: Vec<Budget>,
+    #[serde(default)]
+    hooks: Vec<Hook>,
+    #[serde(default)]
+    notifications: Option<NotifyConfig>,
+    #[serde(default)]
+    log: LogConfig,
+}
+
+impl TryFrom<RawConfig> for Config {
+    type Error = String;
+
+    fn try_from(raw: RawConfig) -> Result<Self, Self::Error> {
+        let mut games = raw.games;
+        for matcher in raw.tracked {
+            // Plain names keep the id they were stored under before games existed.
+            let id = match &matcher {
+                Matcher::Comm(name) => name.clone(),
+                other => other.to_string(),
+            };
+            games.push(Game {
+                id,
+                name: String::new(),
+                matchers: vec![matcher],
+            });
+        }
+
+        let mut ids = HashSet::new();
+        for game in &mut games {
+            if game.id.is_empty() {
+                return Err("game id must not be empty".to_string());
+            }
+            if !ids.insert(game.id.clone()) {
+                return Err(format!("duplicate game id {:?}", game.id));
+            }
+            if game.matchers.is_empty() {
+                return Err(format!("game {:?} has no match rules", game.id));
+            }
+            if game.name.is_empty() {
+                game.name = game.id.clone();
+            }
+        }
+        let poll = raw.poll;
+        // open sessions only get heartbeats when the daemon scans
+        if !(poll.interval > 0.0 && poll.interval <= HEARTBEAT_SECS as f64) {
+            return Err(format!(
+                "poll interval must be more than 0 and at most {} seconds",
+                HEARTBEAT_SECS
+            ));
+        }
+        if poll.idle_interval < poll.interval {
+            return Err("poll idle_interval must not be shorter than interval".to_string());
+        }
+        if raw.idle.as_ref().is_some_and(|idle| idle.threshold == 0) {
+            return Err("idle threshold must be more than 0 seconds".to_string());
+        }
+        for budget in &raw.budgets {
+            if budget.daily.is_none() && budget.weekly.is_none() && budget.windows.is_empty() {
+                return Err("a budget needs daily, weekly or windows".to_string());
+            }
+            if let Some(game) = budget.games.iter().find(|game| !ids.contains(*game)) {
+                return Err(format!("budget for unknown game {:?}", game));
+            }
+            if budget.action == Action::Command && budget.command.is_none() {
+                return Err("a budget with action command needs a command".to_string());
+            }
+        }
+        for hook in &raw.hooks {
+            if hook.on == HookEvent::Every && hook.minutes.is_none_or(|minutes| minutes == 0) {
+                return Err("an every hook needs minutes of more than 0".to_string());
+            }
+            if let Some(game) = hook.games.iter().find(|game| !ids.contains(*game)) {
+                return Err(format!("hook for unknown game {:?}", game));
+            }
+        }
+        let milestones = raw
+            .notifications
+            .iter()
+            .flat_map(|notify| &notify.milestones);
+        for milestone in milestones {
+            if milestone.after <= 0 {
+                return Err("a milestone needs to be after more than 0 seconds".to_string());
+            }
+            if let Some(game) = milestone.games.iter().find(|game| !ids.contains(*game)) {
+                return Err(format!("milestone for unknown game {:?}", game));
+            }
+        }
+        Ok(Config {
+            games,
+            process_source: raw.process_source,
+            poll,
+            idle: raw.idle,
+            budgets: raw.budgets,
+            hooks: raw.hooks,
+            notifications: raw.notifications,
+            log: raw.log,
+        })
+    }
+}
+
+impl Config {
+    /// The first game with a rule matching `proc`, and that rule.
+    pub fn find(&self, proc: &Process) -> Option<(&Game, &Matcher)> {
+        self.games.iter().find_map(|game| {
+            let rule = game.matchers.iter().find(|rule| rule.matches(proc))?;
+            Some((game, rule))
+        })
+    }
+}
+
+pub fn default_path() -> crate::error::Result<PathBuf> {
+    let mut path = dirs::config_dir()
+        .ok_or_else(|| Error::Config("No config dir (is HOME set?); pass --config".into()))?;
+    path.push("playtime-tracker/config.yaml");
+    Ok(path)
+}
+
+/// Loads the config for the daemon. If there is none yet, an example one
+/// is written and an error returned, as there is nothing to track.
+pub fn load_config(path: &Path) -> crate::error::Result<Config> {
+    if !path.exists() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| Error::io(format!("create {}", dir.display()), e))?;
+        }
+        let default_config = r#"
+tracked:
+    - "example_program"
+"#;
+        fs::write(path, default_config)
+            .map_err(|e| Error::io(format!("write {}", path.display()), e))?;
+        info!("Created default config at {:?}", path);
+        return Err(Error::Config(format!(
+            "{} is new; add the programs to track to it",
+            path.display()
+        )));
+    }
+
+    let config = read_config(path)?;
+
+    if config.games.is_empty() {
+        return Err(Error::Config(format!(
+            "no programs to track in {}",
+            path.display()
+        )));
+    }
+    Ok(config)
+}
+
+/// Reads and parses the config without any of the daemon's first-run
+/// handling.
+pub fn read_config(path: &Path) -> crate::error::Result<Config> {
+    let config_str =
+        fs::read_to_string(path).map_err(|e| Error::io(format!("read {}", path.display()), e))?;
+    serde_yaml::from_str(&config_str)
+        .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_config() {
+        let dir = std::env::temp_dir().join(format!("playtime-config-{}", std::process::id()));
+        let path = dir.join("config.yaml");
+        // the first time writes an example to fill in
+        assert!(matches!(load_config(&path), Err(Error::Config(_))));
+        let config = load_config(&path).unwrap();
+        assert!(!config.games.is_empty());
+        fs::write(&path, "tracked: []").unwrap();
+        assert!(matches!(load_config(&path), Err(Error::Config(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_games_and_tracked() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+games:
+    - id: hollow_knight
+      name: Hollow Knight
+      match:
+          - "hollow_knight.x86_64"
+          - cmdline: "HollowKnight\\.exe"
+tracked:
+    - "factorio"
+    - exe: "/usr/bin/supertux2"
+"#,
+        )
+        .unwrap();
+        let games: Vec<(&str, &str)> = config
+            .games
+            .iter()
+            .map(|g| (g.id.as_str(), g.name.as_str()))
+            .collect();
+        assert_eq!(
+            games,
+            [
+                ("hollow_knight", "Hollow Knight"),
+                ("factorio", "factorio"),
+                ("exe:/usr/bin/supertux2", "exe:/usr/bin/supertux2"),
+            ]
+        );
+
+        let proton = Process::fake(2, 1, "wine64", None, Some("Z:\\HollowKnight.exe"));
+        let (game, rule) = config.find(&proton).unwrap();
+        assert_eq!(game.id, "hollow_knight");
+        assert_eq!(rule.to_string(), "cmdline:HollowKnight\\.exe");
+    }
+
+    #[test]
+    fn test_invalid_games() {
+        let duplicate = "games:\n  - {id: a, match: [x]}\n  - {id: a, match: [y]}";
+        assert!(serde_yaml::from_str::<Config>(duplicate).is_err());
+        let no_rules = "games:\n  - {id: a, match: []}";
+        assert!(serde_yaml::from_str::<Config>(no_rules).is_err());
+    }
+
+    #[test]
+    fn test_process_source() {
+        let config: Config = serde_yaml::from_str("tracked: [x]").unwrap();
+        assert_eq!(config.process_source, SourceKind::Auto);
+        let config: Config = serde_yaml::from_str("process_source: poll").unwrap();
+        assert_eq!(config.process_source, SourceKind::Poll);
+        assert!(serde_yaml::from_str::<Config>("process_source: ebpf").is_err());
+    }
+
+    #[test]
+    fn test_poll() {
+        let config: Config = serde_yaml::from_str("tracked: [x]").unwrap();
+        assert_eq!(config.poll, Poll::default());
+        let config: Config = serde_yaml::from_str("poll: {interval: 0.5, adaptive: true}").unwrap();
+        assert_eq!(
+            (config.poll.interval, config.poll.idle_interval),
+            (0.5, 10.0)
+        );
+        assert!(serde_yaml::from_str::<Config>("poll: {interval: 0}").is_err());
+        assert!(serde_yaml::from_str::<Config>("poll: {interval: 60}").is_err());
+        assert!(serde_yaml::from_str::<Config>("poll: {interval: 5, idle_interval: 2}").is_err());
+        assert!(serde_yaml::from_str::<Config>("poll: {every: 5}").is_err());
+    }
+
+    #[test]
+    fn test_budgets() {
+        let games = "games: [{id: factorio, match: [factorio]}]";
+        let parse = |budgets: &str| {
+            serde_yaml::from_str::<Config>(&format!("{{{}, budgets: [{}]}}", games, budgets))
+        };
+        let config = parse("{games: [factorio], daily: 2h, action: terminate}").unwrap();
+        assert_eq!(config.budgets[0].daily, Some(7200));
+        assert!(parse("{games: [factorio]}").is_err());
+        assert!(parse("{games: [tetris], daily: 2h}").is_err());
+        assert!(parse("{weekly: 10h, action: command}").is_err());
+        assert!(parse("{windows: [\"mon-fri 16:00-20:00\"], action: kill}").is_ok());
+    }
+
+    #[test]
+    fn test_hooks() {
+        let games = "games: [{id: factorio, match: [factorio]}]";
+        let parse = |hooks: &str| {
+            serde_yaml::from_str::<Config>(&format!("{{{}, hooks: [{}]}}", games, hooks))
+        };
+        let config = parse("{on: every, minutes: 60, games: [factorio], command: 'true'}").unwrap();
+        assert_eq!(config.hooks[0].minutes, Some(60));
+        assert!(parse("{on: every, command: 'true'}").is_err());
+        assert!(parse("{on: every, minutes: 0, command: 'true'}").is_err());
+        assert!(parse("{on: start, games: [tetris], command: 'true'}").is_err());
+    }
+
+    #[test]
+    fn test_notifications() {
+        let games = "games: [{id: factorio, match: [factorio]}]";
+        let parse = |notifications: &str| {
+            serde_yaml::from_str::<Config>(&format!(
+                "{{{}, notifications: {}}}",
+                games, notifications
+            ))
+        };
+        let config = parse("{milestones: [{after: 2h}], budget_warning: 10m}").unwrap();
+        let notifications = config.notifications.unwrap();
+        assert_eq!(notifications.milestones[0].after, 7200);
+        assert_eq!(notifications.budget_warning, Some(600));
+        assert!(parse("{milestones: [{after: 0}]}").is_err());
+        assert!(parse("{milestones: [{after: 1h, games: [tetris]}]}").is_err());
+        assert!(parse("{milestones: [{games: [factorio]}]}").is_err());
+    }
+}
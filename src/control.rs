@@ -0,0 +1,250 @@
+use crate::tracker::OpenSession;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+/// How long a client may take to send a request or read the reply.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `$XDG_RUNTIME_DIR/playtime-tracker.sock`, or a per-user name in the
+/// temp dir when there is no runtime dir.
+pub fn default_path() -> PathBuf {
+    match dirs::runtime_dir() {
+        Some(dir) => dir.join("playtime-tracker.sock"),
+        None => {
+            // SAFETY: getuid has no preconditions and cannot fail
+            let uid = unsafe { libc::getuid() };
+            std::env::temp_dir().join(format!("playtime-tracker-{}.sock", uid))
+        }
+    }
+}
+
+/// One line of JSON from client to daemon, e.g. `{"command":"pause"}`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    Status,
+    /// Re-read the config file, keeping the current one if it is invalid
+    ReloadConfig,
+    /// Stop counting play time of one game, by id or display name, or of
+    /// all games until `resume`
+    Pause {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        game: Option<String>,
+    },
+    Resume {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        game: Option<String>,
+    },
+    /// Write heartbeats now instead of at the next interval
+    Flush,
+    /// End open sessions and exit
+    Shutdown,
+    /// Sent by the daemon's own process source when it has news; not
+    /// accepted on the socket
+    #[serde(skip)]
+    Scan,
+}
+
+/// One line of JSON from daemon to client.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<DaemonStatus>,
+}
+
+impl Response {
+    pub fn ok() -> Response {
+        Response {
+            ok: true,
+            ..Response::default()
+        }
+    }
+
+    pub fn error(error: String) -> Response {
+        Response {
+            ok: false,
+            error: Some(error),
+            ..Response::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    /// all games are paused
+    pub paused: bool,
+    /// ids of games paused on their own
+    #[serde(default)]
+    pub paused_games: Vec<String>,
+    /// last input, while the user is idle
+    #[serde(default)]
+    pub idle_since: Option<DateTime<Local>>,
+    pub sessions: Vec<OpenSession>,
+}
+
+/// A request waiting for the daemon loop to answer it.
+pub type Pending = (Request, Sender<Response>);
+
+/// The daemon's end of the socket. Connections are served one at a time on
+/// a background thread, which hands each request to the daemon loop through
+/// `requests`. The socket file is removed on drop.
+pub struct Server {
+    path: PathBuf,
+    /// held from reading a request until its response is written, so the
+    /// reply to `shutdown` gets out before the daemon exits
+    busy: Arc<Mutex<()>>,
+}
+
+impl Server {
+    /// Refuses to take over the socket of a daemon that is still running;
+    /// a socket file left behind by one that crashed is replaced.
+    pub fn bind(path: &Path, requests: Sender<Pending>) -> Result<Server, String> {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!(
+                "another daemon is already listening on {}",
+                path.display()
+            ));
+        }
+        let _ = fs::remove_file(path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let listener = UnixListener::bind(path)
+            .map_err(|e| format!("cannot listen on {}: {}", path.display(), e))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(|e| e.to_string())?;
+
+        let busy = Arc::new(Mutex::new(()));
+        let lock = busy.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // a client that misbehaves only loses its own connection
+                let _ = serve(stream, &requests, &lock);
+            }
+        });
+        Ok(Server {
+            path: path.to_path_buf(),
+            busy,
+        })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _busy = self.busy.lock();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Answers each request line on one connection until the client hangs up.
+fn serve(stream: UnixStream, tx: &Sender<Pending>, busy: &Mutex<()>) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let _busy = busy.lock();
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => {
+                let (reply, response) = mpsc::channel();
+                if tx.send((request, reply)).is_err() {
+                    return Ok(());
+                }
+                response
+                    .recv()
+                    .unwrap_or_else(|_| Response::error("daemon is shutting down".to_string()))
+            }
+            Err(e) => Response::error(format!("invalid request: {}", e)),
+        };
+        writeln!(writer, "{}", serde_json::to_string(&response).unwrap())?;
+    }
+    Ok(())
+}
+
+/// Sends one request to the daemon. Fails with `NotFound` or
+/// `ConnectionRefused` when no daemon is running.
+pub fn send(path: &Path, request: &Request) -> io::Result<Response> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    writeln!(stream, "{}", serde_json::to_string(request).unwrap())?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Whether `send` failed because there is no daemon to talk to.
+pub fn not_running(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol() {
+        assert_eq!(
+            serde_json::to_string(&Request::ReloadConfig).unwrap(),
+            r#"{"command":"reload-config"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"command":"pause"}"#).unwrap(),
+            Request::Pause { game: None }
+        );
+        let resume = Request::Resume {
+            game: Some("factorio".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_string(&resume).unwrap(),
+            r#"{"command":"resume","game":"factorio"}"#
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"command":"explode"}"#).is_err());
+        assert_eq!(
+            serde_json::to_string(&Response::ok()).unwrap(),
+            r#"{"ok":true}"#
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("playtime-{}.sock", std::process::id()));
+        let (tx, requests) = mpsc::channel();
+        let server = Server::bind(&path, tx.clone()).unwrap();
+        assert!(Server::bind(&path, tx).is_err());
+
+        let daemon = thread::spawn(move || {
+            let (request, reply) = requests.recv().unwrap();
+            assert_eq!(request, Request::Flush);
+            reply.send(Response::ok()).unwrap();
+        });
+        assert!(send(&path, &Request::Flush).unwrap().ok);
+        daemon.join().unwrap();
+
+        drop(server);
+        assert!(send(&path, &Request::Status).is_err_and(|e| not_running(&e)));
+    }
+}
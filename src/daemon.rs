@@ -0,0 +1,431 @@
+use crate::budget::Enforcer;
+use crate::config::{Config, read_config};
+use crate::control::{DaemonStatus, Pending, Request, Response, Server};
+use crate::db::sync_games;
+use crate::error::{Error, Result};
+use crate::hooks::HookRunner;
+use crate::idle::IdleDetector;
+use crate::log::{self, debug, error, info, warn};
+use crate::matcher::Matcher;
+use crate::notify::Notifier;
+use crate::procfs::Process;
+use crate::signals;
+use crate::source::{self, Polling, ProcessSource};
+use crate::suspend::SuspendDetector;
+use crate::tracker::{EndReason, OpenSession, Tracker};
+use crate::watch::watch_config;
+use chrono::{DateTime, Local};
+use rusqlite::Connection;
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The long-running tracker: scans processes at the configured interval and
+/// answers control requests in between.
+pub struct Daemon {
+    config_path: PathBuf,
+    config: Config,
+    conn: Connection,
+    tracker: Tracker,
+    source: Box<dyn ProcessSource>,
+    /// present when the config has an `idle` section
+    idle: Option<IdleDetector>,
+    suspend: SuspendDetector,
+    budgets: Enforcer,
+    hooks: HookRunner,
+    notifier: Notifier,
+}
+
+impl Daemon {
+    pub fn new(config_path: &Path, config: Config, conn: Connection) -> Daemon {
+        Daemon {
+            config_path: config_path.to_path_buf(),
+            config,
+            conn,
+            tracker: Tracker::default(),
+            source: Box::new(Polling),
+            idle: None,
+            suspend: SuspendDetector::new(Local::now()),
+            budgets: Enforcer::default(),
+            hooks: HookRunner::default(),
+            notifier: Notifier::default(),
+        }
+    }
+
+    /// Runs until a `shutdown` request or SIGTERM/SIGINT arrives. Fails
+    /// only while starting up; after that, errors are logged and what
+    /// failed is tried again at the next scan.
+    pub fn run(mut self, socket: &Path) -> Result<()> {
+        if let Err(e) = log::init(&self.config.log) {
+            error!("{}", e);
+        }
+        let (tx, requests) = mpsc::channel();
+        signals::forward(tx.clone());
+        if let Err(e) = watch_config(&self.config_path, tx.clone()) {
+            warn!(
+                "Not watching {} for changes: {}",
+                self.config_path.display(),
+                e
+            );
+        }
+        let _server = Server::bind(socket, tx.clone())
+            .map_err(|e| Error::Daemon(format!("Cannot start daemon: {}", e)))?;
+        // only once the socket is ours, so a second daemon cannot close
+        // sessions the first one is still tracking
+        self.tracker = Tracker::recover(&self.conn, Process::read)?;
+        self.source = source::open(self.config.process_source, tx);
+        self.open_idle(Local::now());
+        loop {
+            self.scan();
+            if self.wait(&requests) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn scan(&mut self) {
+        let now = Local::now();
+        // before anything ends: a game that quit right after resume still
+        // slept through the suspend
+        if let Some((from, to)) = self.suspend.check(now) {
+            info!("System was asleep from {} to {}", from, to);
+            if let Err(e) = self.tracker.record_suspend(&self.conn, from, to) {
+                error!("Cannot record the suspend: {}", e);
+            }
+        }
+        let procs = match self.source.processes() {
+            Ok(procs) => procs,
+            Err(e) => {
+                // ending every session would be wrong; try again next time
+                error!("{}", e);
+                return;
+            }
+        };
+        debug!("Scanned {} processes", procs.len());
+        let before = self.tracker.open_sessions();
+        if let Err(e) = self.tracker.update(&self.conn, &self.config, &procs, now) {
+            error!("Cannot record sessions: {}", e);
+        }
+        // some changes may have been written before it failed
+        self.sessions_changed(&before, now);
+        let rules: Vec<Matcher> = self
+            .config
+            .games
+            .iter()
+            .flat_map(|game| game.matchers.iter().cloned())
+            .collect();
+        let interval = self.interval();
+        self.source
+            .watch(&rules, &self.tracker.processes(&[]), interval);
+        if let (Some(detector), Some(config)) = (&mut self.idle, &self.config.idle) {
+            let last_input = detector.last_input(now);
+            let idle = (now - last_input).num_seconds() >= config.threshold as i64;
+            if let Err(e) = self.tracker.set_idle(&self.conn, last_input, idle) {
+                error!("Cannot record idle time: {}", e);
+            }
+        }
+        let remaining = self
+            .budgets
+            .check(&self.conn, &self.config.budgets, &self.tracker, now);
+        let (hooks, budgets) = (&self.config.hooks, &self.config.budgets);
+        let sessions = self.tracker.open_sessions();
+        self.hooks.tick(hooks, &sessions, now);
+        self.hooks.budgets(hooks, budgets, &remaining);
+        if let Some(notifications) = &self.config.notifications {
+            self.notifier
+                .tick(notifications, &sessions, budgets, &remaining, now);
+        }
+    }
+
+    /// Runs the start and end hooks of sessions that opened or closed since
+    /// `before` was taken.
+    fn sessions_changed(&mut self, before: &[OpenSession], now: DateTime<Local>) {
+        let after = self.tracker.open_sessions();
+        let same = |a: &OpenSession, b: &OpenSession| a.game == b.game && a.started == b.started;
+        for session in before {
+            if !after.iter().any(|open| same(open, session)) {
+                self.hooks.ended(&self.config.hooks, session, now);
+            }
+        }
+        for session in &after {
+            if !before.iter().any(|open| same(open, session)) {
+                self.hooks.started(&self.config.hooks, session, now);
+            }
+        }
+    }
+
+    /// (Re)starts idle detection as configured. Without it, or if no input
+    /// source can be read, all time counts as active.
+    fn open_idle(&mut self, now: DateTime<Local>) {
+        self.idle =
+            self.config
+                .idle
+                .as_ref()
+                .and_then(|config| match IdleDetector::open(config, now) {
+                    Ok(detector) => {
+                        info!("Detecting idle time from {}", detector.source());
+                        Some(detector)
+                    }
+                    Err(e) => {
+                        warn!("Idle detection disabled: {}", e);
+                        None
+                    }
+                });
+        if self.idle.is_none()
+            && let Err(e) = self.tracker.set_idle(&self.conn, now, false)
+        {
+            error!("Cannot record idle time: {}", e);
+        }
+    }
+
+    /// Time until the next scan. In adaptive mode that is long while nothing
+    /// is running and short while a game is, so a laptop is left alone most
+    /// of the time but sessions still end close to when the game exits.
+    fn interval(&self) -> Duration {
+        let poll = &self.config.poll;
+        let secs = if poll.adaptive && !self.tracker.is_tracking() {
+            poll.idle_interval
+        } else {
+            poll.interval
+        };
+        Duration::from_secs_f64(secs)
+    }
+
+    /// Sleeps until the next scan, answering requests as they come in.
+    /// Returns true once asked to shut down.
+    fn wait(&mut self, requests: &Receiver<Pending>) -> bool {
+        let since = Instant::now();
+        loop {
+            // a request may have started a game or changed the config
+            let timeout = (since + self.interval()).saturating_duration_since(Instant::now());
+            match requests.recv_timeout(timeout) {
+                Ok((request, reply)) => {
+                    let shutdown = request == Request::Shutdown;
+                    let _ = reply.send(self.handle(request));
+                    if shutdown {
+                        return true;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return false,
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(timeout);
+                    return false;
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, request: Request) -> Response {
+        let now = Local::now();
+        match request {
+            Request::Status => Response {
+                status: Some(DaemonStatus {
+                    pid: std::process::id(),
+                    paused: self.tracker.is_paused_all(),
+                    paused_games: self.tracker.paused_games(),
+                    idle_since: self.tracker.idle_since(),
+                    sessions: self.tracker.open_sessions(),
+                }),
+                ..Response::ok()
+            },
+            Request::ReloadConfig => match read_config(&self.config_path)
+                .and_then(|config| sync_games(&config, &self.conn).map(|_| config))
+            {
+                Ok(config) => {
+                    let before = self.tracker.open_sessions();
+                    if let Err(e) = self.tracker.apply_config(&self.conn, &config, now) {
+                        // the sessions left over end at the next scan
+                        error!("Cannot end sessions of removed games: {}", e);
+                    }
+                    let idle_changed = config.idle != self.config.idle;
+                    let budgets_changed = config.budgets != self.config.budgets;
+                    if budgets_changed {
+                        self.budgets.reset();
+                    }
+                    let hooks_changed = config.hooks != self.config.hooks;
+                    if hooks_changed {
+                        let sessions = self.tracker.open_sessions();
+                        self.hooks.reset_every(&config.hooks, &sessions, now);
+                    }
+                    if budgets_changed || hooks_changed {
+                        self.hooks.reset_warnings();
+                    }
+                    if budgets_changed {
+                        self.notifier.reset_budgets();
+                    }
+                    if config.notifications != self.config.notifications
+                        && let Some(notifications) = &config.notifications
+                    {
+                        let sessions = self.tracker.open_sessions();
+                        self.notifier
+                            .reset_milestones(notifications, &sessions, now);
+                    }
+                    if config.log != self.config.log
+                        && let Err(e) = log::init(&config.log)
+                    {
+                        error!("{}", e);
+                    }
+                    self.config = config;
+                    // sessions of games that were removed end with the new
+                    // config's hooks
+                    self.sessions_changed(&before, now);
+                    if idle_changed {
+                        self.open_idle(now);
+                    }
+                    info!("Reloaded config from {}", self.config_path.display());
+                    Response::ok()
+                }
+                Err(e) => {
+                    error!("Keeping the current config: {}", e);
+                    Response::error(e.to_string())
+                }
+            },
+            Request::Pause { game } => match self.find_game(game.as_deref()) {
+                Ok(game) => {
+                    if let Err(e) = self
+                        .tracker
+                        .set_paused(&self.conn, game.as_deref(), true, now)
+                    {
+                        return Response::error(e.to_string());
+                    }
+                    info!(
+                        "Paused tracking of {} at {}",
+                        game.as_deref().unwrap_or("all games"),
+                        now
+                    );
+                    Response::ok()
+                }
+                Err(e) => Response::error(e),
+            },
+            Request::Resume { game } => match self.find_game(game.as_deref()) {
+                Ok(Some(_)) if self.tracker.is_paused_all() => Response::error(
+                    "All games are paused; resume them all before picking out one".to_string(),
+                ),
+                Ok(game) => {
+                    if let Err(e) = self
+                        .tracker
+                        .set_paused(&self.conn, game.as_deref(), false, now)
+                    {
+                        return Response::error(e.to_string());
+                    }
+                    info!(
+                        "Resumed tracking of {} at {}",
+                        game.as_deref().unwrap_or("all games"),
+                        now
+                    );
+                    Response::ok()
+                }
+                Err(e) => Response::error(e),
+            },
+            Request::Scan => {
+                self.scan();
+                Response::ok()
+            }
+            Request::Flush => match self.tracker.flush(&self.conn, now) {
+                Ok(()) => Response::ok(),
+                Err(e) => Response::error(e.to_string()),
+            },
+            Request::Shutdown => {
+                let before = self.tracker.open_sessions();
+                let ended = self
+                    .tracker
+                    .end_all(&self.conn, now, EndReason::DaemonStopped);
+                self.sessions_changed(&before, now);
+                info!("Shutting down at {}", now);
+                match ended {
+                    Ok(()) => Response::ok(),
+                    // what is left open is closed as orphaned next start
+                    Err(e) => {
+                        error!("Cannot end sessions: {}", e);
+                        Response::error(e.to_string())
+                    }
+                }
+            }
+        }
+    }
+
+    /// The id of the configured game named by `game`, which may be its id
+    /// or its display name; `None` stands for all games.
+    fn find_game(&self, game: Option<&str>) -> std::result::Result<Option<String>, String> {
+        let Some(game) = game else {
+            return Ok(None);
+        };
+        self.config
+            .games
+            .iter()
+            .find(|candidate| candidate.id == game || candidate.name == game)
+            .map(|found| Some(found.id.clone()))
+            .ok_or_else(|| format!("No game {:?} in the config", game))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::open_in_memory;
+
+    #[test]
+    fn test_pause_and_reload() {
+        let config: Config =
+            serde_yaml::from_str("games: [{id: factorio, match: [factorio]}]").unwrap();
+        let mut daemon = Daemon::new(
+            Path::new("/nonexistent/config.yaml"),
+            config,
+            open_in_memory(),
+        );
+        let scan = [Process::fake(10, 1, "factorio", None, None)];
+        daemon
+            .tracker
+            .update(&daemon.conn, &daemon.config, &scan, Local::now())
+            .unwrap();
+
+        let pause = |game: Option<&str>| Request::Pause {
+            game: game.map(String::from),
+        };
+        let resume = |game: Option<&str>| Request::Resume {
+            game: game.map(String::from),
+        };
+        assert!(daemon.handle(pause(None)).ok);
+        // the session stays open, it just stops counting
+        let status = daemon.handle(Request::Status).status.unwrap();
+        assert!(status.paused && status.sessions.len() == 1 && status.sessions[0].paused);
+        assert!(!daemon.handle(resume(Some("factorio"))).ok);
+        assert!(daemon.handle(resume(None)).ok);
+        assert!(!daemon.tracker.is_paused("factorio"));
+
+        assert!(!daemon.handle(pause(Some("tetris"))).ok);
+        assert!(daemon.handle(pause(Some("factorio"))).ok);
+        let status = daemon.handle(Request::Status).status.unwrap();
+        assert!(!status.paused && status.paused_games == ["factorio"]);
+
+        // an unreadable config is reported and the old one kept
+        let response = daemon.handle(Request::ReloadConfig);
+        assert!(!response.ok && response.error.is_some());
+        assert_eq!(daemon.config.games.len(), 1);
+    }
+
+    #[test]
+    fn test_adaptive_interval() {
+        let config: Config = serde_yaml::from_str(
+            "{games: [{id: factorio, match: [factorio]}], poll: {interval: 2, adaptive: true}}",
+        )
+        .unwrap();
+        let mut daemon = Daemon::new(Path::new("config.yaml"), config, open_in_memory());
+        assert_eq!(daemon.interval(), Duration::from_secs(10));
+        let scan = [Process::fake(10, 1, "factorio", None, None)];
+        daemon
+            .tracker
+            .update(&daemon.conn, &daemon.config, &scan, Local::now())
+            .unwrap();
+        assert_eq!(daemon.interval(), Duration::from_secs(2));
+        daemon.config.poll.adaptive = false;
+        daemon
+            .tracker
+            .update(&daemon.conn, &daemon.config, &[], Local::now())
+            .unwrap();
+        assert_eq!(daemon.interval(), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,494 @@
+use crate::config::Config;
+use crate::error::{Error, Result, is_busy};
+use crate::log::{info, warn};
+use crate::period::{Period, merge, subtract};
+use chrono::{DateTime, Local};
+use rusqlite::{Connection, Transaction, params};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// Schema changes, oldest first. Migration `i` takes the database from
+/// `user_version` i to i + 1; new ones are only ever appended.
+const MIGRATIONS: &[fn(&Transaction) -> rusqlite::Result<()>] =
+    &[legacy_schema, end_reason, gaps, segments];
+
+/// The schema version this binary writes.
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+pub fn default_path() -> Result<PathBuf> {
+    let mut db_path = dirs::data_local_dir()
+        .ok_or_else(|| Error::Config("No data dir (is HOME set?); pass --db".into()))?;
+    db_path.push("playtime-tracker");
+    db_path.set_extension("sqlite");
+    Ok(db_path)
+}
+
+/// How long SQLite itself waits for a lock before `retry` takes over.
+const BUSY_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Backoff of `retry`: the first wait, doubled each time, and how many.
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+const RETRIES: u32 = 6;
+
+pub fn init_db(db_path: &Path) -> Result<Connection> {
+    if !db_path.exists()
+        && let Some(dir) = db_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+    {
+        fs::create_dir_all(dir).map_err(|e| Error::io(format!("create {}", dir.display()), e))?;
+        info!("Created data dir at {:?}", dir);
+    }
+
+    let mut conn = Connection::open(db_path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    migrate(&mut conn)?;
+    Ok(conn)
+}
+
+/// Runs `query`, trying again with backoff while another connection,
+/// such as a backup or report, holds a lock on the database.
+fn retry<T>(mut query: impl FnMut() -> rusqlite::Result<T>) -> rusqlite::Result<T> {
+    let mut delay = RETRY_DELAY;
+    for _ in 0..RETRIES {
+        match query() {
+            Err(e) if is_busy(&e) => {
+                warn!("Database is busy, trying again in {:?}", delay);
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    query()
+}
+
+/// Runs `write` in a transaction, retried as a whole by `retry`, so a
+/// change that takes several statements is made completely or not at all.
+pub fn write<T>(
+    conn: &Connection,
+    mut write: impl FnMut(&Connection) -> rusqlite::Result<T>,
+) -> Result<T> {
+    Ok(retry(|| {
+        let tx = conn.unchecked_transaction()?;
+        let value = write(&tx)?;
+        tx.commit()?;
+        Ok(value)
+    })?)
+}
+
+pub fn schema_version(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+/// Brings the schema up to `SCHEMA_VERSION`, one transaction per migration.
+/// A file with history in it is copied next to itself before the first
+/// change; a database from a newer binary is left untouched.
+pub fn migrate(conn: &mut Connection) -> Result<()> {
+    let version = schema_version(conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::Schema(format!(
+            "database schema version {} is newer than this binary supports ({})",
+            version, SCHEMA_VERSION
+        )));
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    let has_data: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'sessions')",
+        [],
+        |row| row.get(0),
+    )?;
+    if let Some(path) = conn.path().filter(|path| has_data && !path.is_empty()) {
+        let backup = format!("{}.v{}.bak", path, version);
+        if !Path::new(&backup).exists() {
+            conn.execute("VACUUM INTO ?1", [&backup]).map_err(|e| {
+                Error::Schema(format!("failed to back up database to {}: {}", backup, e))
+            })?;
+            info!("Backed up database to {}", backup);
+        }
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let to = from + 1;
+        let tx = conn.transaction()?;
+        migration(&tx)
+            .and_then(|_| tx.pragma_update(None, "user_version", to))
+            .and_then(|_| tx.commit())
+            .map_err(|e| {
+                Error::Schema(format!("migration to schema version {} failed: {}", to, e))
+            })?;
+    }
+    Ok(())
+}
+
+/// Everything from before schema versions existed. Those databases can be
+/// in any intermediate state, so this only adds what is missing.
+fn legacy_schema(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            id       INTEGER PRIMARY KEY,
+            path     TEXT NOT NULL,
+            pid      INTEGER NOT NULL,
+            proc_started TEXT,
+            started  TEXT NOT NULL,
+            ended    TEXT,
+            rule     TEXT,
+            game     TEXT REFERENCES games(id),
+            heartbeat TEXT
+        );
+        CREATE TABLE IF NOT EXISTS games (
+            id       TEXT PRIMARY KEY,
+            name     TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS session_processes (
+            id         INTEGER PRIMARY KEY,
+            session_id INTEGER NOT NULL REFERENCES sessions(id),
+            pid        INTEGER NOT NULL,
+            comm       TEXT NOT NULL,
+            starttime  INTEGER,
+            proc_started TEXT,
+            rule       TEXT,
+            started    TEXT NOT NULL,
+            ended      TEXT
+        );",
+    )?;
+    add_column_if_missing(tx, "sessions", "rule", "TEXT")?;
+    add_column_if_missing(tx, "sessions", "game", "TEXT REFERENCES games(id)")?;
+    add_column_if_missing(tx, "sessions", "heartbeat", "TEXT")?;
+    add_column_if_missing(tx, "session_processes", "starttime", "INTEGER")?;
+    add_column_if_missing(tx, "sessions", "proc_started", "TEXT")?;
+    add_column_if_missing(tx, "session_processes", "proc_started", "TEXT")?;
+    // sessions recorded before games existed were keyed by comm, which is
+    // also the id a plain `tracked` entry gets
+    tx.execute_batch(
+        "INSERT OR IGNORE INTO games (id, name)
+            SELECT DISTINCT path, path FROM sessions WHERE game IS NULL;
+        UPDATE sessions SET game = path WHERE game IS NULL;",
+    )
+}
+
+/// Why a session ended; NULL for sessions closed before this existed.
+fn end_reason(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE sessions ADD COLUMN end_reason TEXT;")
+}
+
+/// Stretches of a session that do not count as play, such as idle time.
+fn gaps(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE gaps (
+            id         INTEGER PRIMARY KEY,
+            session_id INTEGER NOT NULL REFERENCES sessions(id),
+            kind       TEXT NOT NULL,
+            started    TEXT NOT NULL,
+            ended      TEXT
+        );
+        CREATE INDEX gaps_session ON gaps(session_id);",
+    )
+}
+
+/// Replaces `gaps` with `segments`, which cover each session from start to
+/// end: the time between the old gaps becomes active, and where idle and
+/// suspended gaps overlap the machine counts as suspended.
+fn segments(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE segments (
+            id         INTEGER PRIMARY KEY,
+            session_id INTEGER NOT NULL REFERENCES sessions(id),
+            kind       TEXT NOT NULL,
+            started    TEXT NOT NULL,
+            ended      TEXT
+        );
+        CREATE INDEX segments_session ON segments(session_id);",
+    )?;
+    let parse = |s: &str| {
+        DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|time| time.with_timezone(&Local))
+    };
+    let sessions: Vec<(i64, String, Option<String>)> = tx
+        .prepare("SELECT id, started, ended FROM sessions")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut gaps_of = tx.prepare("SELECT kind, started, ended FROM gaps WHERE session_id = ?1")?;
+    let mut insert = tx.prepare(
+        "INSERT INTO segments (session_id, kind, started, ended) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+
+    for (id, started, ended) in sessions {
+        let gaps: Vec<Stretch<String>> = gaps_of
+            .query_map([id], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?
+            .filter_map(|row| {
+                let (kind, from, to) = row.ok()?;
+                Some((kind, parse(&from)?, to.as_deref().and_then(parse)))
+            })
+            .collect();
+        let Some(start) = parse(&started).filter(|_| !gaps.is_empty()) else {
+            insert.execute(params![id, "active", started, ended])?;
+            continue;
+        };
+        // an open session runs up to the latest time recorded for it
+        let end = ended.as_deref().and_then(parse).unwrap_or_else(|| {
+            gaps.iter()
+                .flat_map(|(_, from, to)| [Some(*from), *to])
+                .flatten()
+                .fold(start, DateTime::max)
+        });
+        let periods = |kind: &str| {
+            merge(
+                gaps.iter()
+                    .filter(|gap| gap.0 == kind)
+                    .map(|(_, from, to)| ((*from).max(start), to.unwrap_or(end).min(end)))
+                    .filter(|(from, to)| from < to)
+                    .collect(),
+            )
+        };
+        let asleep = periods("suspended");
+        let idle = subtract(&periods("idle"), &asleep);
+        let not_active = merge(asleep.iter().chain(&idle).copied().collect());
+        let mut pieces: Vec<Stretch<&str>> = [
+            ("suspended", asleep),
+            ("idle", idle),
+            ("active", subtract(&[(start, end)], &not_active)),
+        ]
+        .into_iter()
+        .flat_map(|(kind, periods): (&str, Vec<Period>)| {
+            periods
+                .into_iter()
+                .map(move |(from, to)| (kind, from, Some(to)))
+        })
+        .collect();
+        pieces.sort_by_key(|piece| piece.1);
+
+        // an open session stays open in whatever it was doing last
+        if ended.is_none() {
+            let kind = gaps
+                .iter()
+                .find(|gap| gap.2.is_none())
+                .map_or("active", |gap| gap.0.as_str());
+            match pieces.last_mut() {
+                Some(last) if last.0 == kind => last.2 = None,
+                _ => pieces.push((kind, end, None)),
+            }
+        }
+        for (kind, from, to) in pieces {
+            insert.execute(params![
+                id,
+                kind,
+                from.to_rfc3339(),
+                to.map(|to| to.to_rfc3339())
+            ])?;
+        }
+    }
+    tx.execute_batch("DROP TABLE gaps;")
+}
+
+/// Kind, start and (unless still open) end of part of a session.
+type Stretch<K> = (K, DateTime<Local>, Option<DateTime<Local>>);
+
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    decl: &str,
+) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1",
+        table
+    ))?;
+    if !stmt.exists([column])? {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {};",
+            table, column, decl
+        ))?;
+    }
+    Ok(())
+}
+
+/// Records the configured games so reports can show their display names.
+pub fn sync_games(config: &Config, conn: &Connection) -> Result<()> {
+    write(conn, |tx| {
+        for game in &config.games {
+            tx.execute(
+                "INSERT INTO games (id, name) VALUES (?1, ?2)
+                ON CONFLICT(id) DO UPDATE SET name = excluded.name",
+                params![game.id, game.name],
+            )?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+pub fn open_in_memory() -> Connection {
+    let mut conn = Connection::open_in_memory().unwrap();
+    migrate(&mut conn).unwrap();
+    conn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("playtime-{}-{}.sqlite", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(format!("{}.v0.bak", path.display()));
+        path
+    }
+
+    #[test]
+    fn test_init_db() {
+        let path = temp_db("init");
+        let conn = init_db(&path).unwrap();
+        let one: i64 = conn.query_row("SELECT 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(one, 1);
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_waits_for_lock() {
+        let path = temp_db("busy");
+        let conn = init_db(&path).unwrap();
+        let other = Connection::open(&path).unwrap();
+        other.execute_batch("BEGIN EXCLUSIVE").unwrap();
+        let holder = thread::spawn(move || {
+            thread::sleep(BUSY_TIMEOUT + Duration::from_millis(200));
+            other.execute_batch("COMMIT").unwrap();
+        });
+        // longer than SQLite's own busy timeout, so retried at least once
+        let written = write(&conn, |tx| {
+            tx.execute(
+                "INSERT INTO games (id, name) VALUES ('factorio', 'Factorio')",
+                [],
+            )
+        });
+        assert_eq!(written.unwrap(), 1);
+        holder.join().unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_legacy_database() {
+        let path = temp_db("legacy");
+        let mut conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE sessions (
+                id       INTEGER PRIMARY KEY,
+                path     TEXT NOT NULL,
+                pid      INTEGER NOT NULL,
+                started  TEXT NOT NULL,
+                ended    TEXT
+            );
+            INSERT INTO sessions (path, pid, started, ended)
+                VALUES ('factorio', 42, '2025-01-01T10:00:00+01:00', '2025-01-01T11:00:00+01:00');",
+        )
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        let game: String = conn
+            .query_row(
+                "SELECT games.name FROM sessions JOIN games ON games.id = sessions.game",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(game, "factorio");
+
+        let backup = format!("{}.v0.bak", path.display());
+        let old = Connection::open(&backup).unwrap();
+        assert_eq!(schema_version(&old).unwrap(), 0);
+        drop(old);
+
+        // already current: nothing to do
+        migrate(&mut conn).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&backup).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_gaps_to_segments() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+        for migration in &MIGRATIONS[..3] {
+            migration(&tx).unwrap();
+        }
+        tx.pragma_update(None, "user_version", 3).unwrap();
+        tx.commit().unwrap();
+        conn.execute_batch(
+            "INSERT INTO sessions (path, pid, started, ended) VALUES
+                ('a', 1, '2025-06-01T10:00:00+00:00', '2025-06-01T13:00:00+00:00'),
+                ('b', 2, '2025-06-01T10:00:00+00:00', NULL);
+            INSERT INTO gaps (session_id, kind, started, ended) VALUES
+                (1, 'idle', '2025-06-01T10:30:00+00:00', '2025-06-01T11:30:00+00:00'),
+                (1, 'suspended', '2025-06-01T11:00:00+00:00', '2025-06-01T12:00:00+00:00'),
+                (2, 'idle', '2025-06-01T11:00:00+00:00', NULL);",
+        )
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT session_id, kind, started, ended FROM segments ORDER BY id")
+            .unwrap();
+        let utc = |s: &str| {
+            DateTime::parse_from_rfc3339(s)
+                .unwrap()
+                .with_timezone(&chrono::Utc)
+                .to_rfc3339()
+        };
+        let segments: Vec<(i64, String, String, Option<String>)> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    utc(&row.get::<_, String>(2)?),
+                    row.get::<_, Option<String>>(3)?.map(|s| utc(&s)),
+                ))
+            })
+            .unwrap()
+            .map(rusqlite::Result::unwrap)
+            .collect();
+        let segment = |id, kind: &str, from: &str, to: Option<&str>| {
+            let at = |hm: &str| format!("2025-06-01T{}:00+00:00", hm);
+            (id, kind.to_string(), at(from), to.map(at))
+        };
+        assert_eq!(
+            segments,
+            [
+                segment(1, "active", "10:00", Some("10:30")),
+                segment(1, "idle", "10:30", Some("11:00")),
+                segment(1, "suspended", "11:00", Some("12:00")),
+                segment(1, "active", "12:00", Some("13:00")),
+                segment(2, "active", "10:00", Some("11:00")),
+                segment(2, "idle", "11:00", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_refuse_newer_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        assert!(migrate(&mut conn).is_err());
+        let tables: i64 = conn
+            .query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tables, 0);
+    }
+}
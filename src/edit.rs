@@ -0,0 +1,294 @@
+use crate::cli::Filter;
+use crate::db::write;
+use crate::error::{Error, Result};
+use crate::period::{Period, subtract};
+use crate::report::{SessionRow, load_sessions};
+use crate::tracker::{EndReason, SegmentKind};
+use chrono::{DateTime, Local};
+use rusqlite::{Connection, OptionalExtension, params};
+
+/// Records a session the daemon did not see, such as one played on a
+/// console. All of it counts as active.
+pub fn add_session(
+    conn: &Connection,
+    game: &str,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+) -> Result<i64> {
+    let game = find_game(conn, game)?;
+    check_times(start, end)?;
+    check_overlap(conn, &game, (start, end), None)?;
+
+    write(conn, |tx| {
+        tx.execute(
+            "INSERT INTO sessions (path, pid, started, ended, game, end_reason)
+            VALUES (?1, 0, ?2, ?3, ?1, ?4)",
+            params![
+                game,
+                start.to_rfc3339(),
+                end.to_rfc3339(),
+                EndReason::Manual.as_str()
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+        insert_segment(tx, id, SegmentKind::Active.as_str(), (start, end))?;
+        Ok(id)
+    })
+}
+
+/// Moves a finished session to another game or other times. Its segments
+/// are cut to the new times; time added at either end counts as active.
+/// Moved to another game, its executable is no longer known and it is
+/// recorded under the game id, as added sessions are.
+pub fn edit_session(
+    conn: &Connection,
+    id: i64,
+    game: Option<&str>,
+    start: Option<DateTime<Local>>,
+    end: Option<DateTime<Local>>,
+) -> Result<()> {
+    let (session, ended) = finished_session(conn, id)?;
+    let game = match game {
+        Some(game) => find_game(conn, game)?,
+        None => session.game.clone(),
+    };
+    let start = start.unwrap_or(session.started);
+    let end = end.unwrap_or(ended);
+    check_times(start, end)?;
+    check_overlap(conn, &game, (start, end), Some(id))?;
+
+    // what the session was doing in the part of the new times it covered
+    let mut segments: Vec<(String, Period)> = session
+        .segments
+        .iter()
+        .filter_map(|segment| {
+            let from = segment.started.max(start);
+            let to = segment.ended.unwrap_or(end).min(end);
+            (from < to).then(|| (segment.kind.clone(), (from, to)))
+        })
+        .collect();
+    let covered: Vec<Period> = segments.iter().map(|(_, period)| *period).collect();
+    let active = SegmentKind::Active.as_str().to_string();
+    segments.extend(
+        subtract(&[(start, end)], &covered)
+            .into_iter()
+            .map(|period| (active.clone(), period)),
+    );
+    segments.sort_by_key(|(_, (from, _))| *from);
+
+    write(conn, |tx| {
+        tx.execute(
+            "UPDATE sessions
+            SET path = CASE WHEN game = ?1 THEN path ELSE ?1 END,
+                game = ?1, started = ?2, ended = ?3
+            WHERE id = ?4",
+            params![game, start.to_rfc3339(), end.to_rfc3339(), id],
+        )?;
+        tx.execute("DELETE FROM segments WHERE session_id = ?1", [id])?;
+        for (kind, period) in &segments {
+            insert_segment(tx, id, kind, *period)?;
+        }
+        Ok(())
+    })
+}
+
+/// Removes a finished session with its processes and segments.
+pub fn delete_session(conn: &Connection, id: i64) -> Result<()> {
+    finished_session(conn, id)?;
+    write(conn, |tx| {
+        for sql in [
+            "DELETE FROM segments WHERE session_id = ?1",
+            "DELETE FROM session_processes WHERE session_id = ?1",
+            "DELETE FROM sessions WHERE id = ?1",
+        ] {
+            tx.execute(sql, [id])?;
+        }
+        Ok(())
+    })
+}
+
+/// The session with `id`, as long as no daemon is still tracking it.
+fn finished_session(conn: &Connection, id: i64) -> Result<(SessionRow, DateTime<Local>)> {
+    let session = load_sessions(conn, &Filter::default())?
+        .into_iter()
+        .find(|session| session.id == id)
+        .ok_or_else(|| Error::Invalid(format!("No session {}", id)))?;
+    let Some(ended) = session.ended else {
+        return Err(Error::Invalid(format!(
+            "Session {} is still open; stop or pause tracking it instead",
+            id
+        )));
+    };
+    Ok((session, ended))
+}
+
+/// The id of the game named `game`, by id or display name.
+fn find_game(conn: &Connection, game: &str) -> Result<String> {
+    conn.query_row(
+        "SELECT id FROM games WHERE id = ?1 OR name = ?1 ORDER BY id = ?1 DESC",
+        [game],
+        |row| row.get(0),
+    )
+    .optional()?
+    .ok_or_else(|| {
+        Error::Invalid(format!(
+            "Unknown game {:?}; add it to the config first",
+            game
+        ))
+    })
+}
+
+fn check_times(start: DateTime<Local>, end: DateTime<Local>) -> Result<()> {
+    if end <= start {
+        return Err(Error::Invalid(
+            "A session has to end after it starts".to_string(),
+        ));
+    }
+    if end > Local::now() {
+        return Err(Error::Invalid(
+            "A session cannot end in the future".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Fails if another session of `game` overlaps `period`. Sessions that are
+/// still open reach up to now.
+fn check_overlap(
+    conn: &Connection,
+    game: &str,
+    (start, end): Period,
+    except: Option<i64>,
+) -> Result<()> {
+    let filter = Filter {
+        since: Some(start),
+        until: Some(end),
+        games: vec![game.to_string()],
+        ..Filter::default()
+    };
+    let now = Local::now();
+    let overlapping = load_sessions(conn, &filter)?.into_iter().find(|session| {
+        Some(session.id) != except
+            && session.game == game
+            && session.started < end
+            && session.end_at(now) > start
+    });
+    match overlapping {
+        Some(session) => Err(Error::Invalid(format!(
+            "Overlaps session {} of {} ({} to {})",
+            session.id,
+            session.name,
+            session.started.format("%Y-%m-%d %H:%M"),
+            session.end_at(now).format("%Y-%m-%d %H:%M")
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn insert_segment(
+    conn: &Connection,
+    id: i64,
+    kind: &str,
+    (from, to): Period,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO segments (session_id, kind, started, ended) VALUES (?1, ?2, ?3, ?4)",
+        params![id, kind, from.to_rfc3339(), to.to_rfc3339()],
+    )
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::open_in_memory;
+    use crate::period::parse_time;
+
+    fn time(s: &str) -> DateTime<Local> {
+        parse_time(&format!("2025-06-01T{}:00+00:00", s)).unwrap()
+    }
+
+    fn setup() -> Connection {
+        let conn = open_in_memory();
+        conn.execute_batch(
+            "INSERT INTO games (id, name) VALUES ('factorio', 'Factorio'), ('tetris', 'Tetris');",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_add_session() {
+        let conn = setup();
+        let id = add_session(&conn, "Factorio", time("10:00"), time("12:00")).unwrap();
+        let sessions = load_sessions(&conn, &Filter::default()).unwrap();
+        assert_eq!(sessions[0].id, id);
+        assert_eq!(sessions[0].game, "factorio");
+        assert_eq!(sessions[0].end_reason.as_deref(), Some("manual"));
+        assert_eq!(sessions[0].durations(), Some((7200, 0)));
+
+        assert!(add_session(&conn, "factorio", time("11:00"), time("13:00")).is_err());
+        assert!(add_session(&conn, "factorio", time("12:00"), time("10:00")).is_err());
+        assert!(add_session(&conn, "doom", time("12:00"), time("13:00")).is_err());
+        // touching is fine, and so is another game at the same time
+        add_session(&conn, "factorio", time("12:00"), time("13:00")).unwrap();
+        add_session(&conn, "tetris", time("11:00"), time("13:00")).unwrap();
+    }
+
+    #[test]
+    fn test_edit_session() {
+        let conn = setup();
+        let id = add_session(&conn, "factorio", time("10:00"), time("12:00")).unwrap();
+        conn.execute_batch(
+            "UPDATE segments SET ended = '2025-06-01T11:00:00+00:00';
+            INSERT INTO segments (session_id, kind, started, ended)
+                VALUES (1, 'idle', '2025-06-01T11:00:00+00:00', '2025-06-01T12:00:00+00:00');",
+        )
+        .unwrap();
+        let other = add_session(&conn, "factorio", time("13:00"), time("14:00")).unwrap();
+
+        // cut at the start, extended at the end
+        edit_session(&conn, id, None, Some(time("10:30")), Some(time("12:30"))).unwrap();
+        let session = &load_sessions(&conn, &Filter::default()).unwrap()[0];
+        assert_eq!(
+            (session.started, session.ended),
+            (time("10:30"), Some(time("12:30")))
+        );
+        assert_eq!(session.durations(), Some((1800 + 1800, 3600)));
+
+        assert!(edit_session(&conn, id, None, None, Some(time("13:30"))).is_err());
+        conn.execute("UPDATE sessions SET path = 'factorio.exe'", [])
+            .unwrap();
+        edit_session(&conn, other, Some("tetris"), None, None).unwrap();
+        let path = |id: i64| -> String {
+            conn.query_row("SELECT path FROM sessions WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!(path(other), "tetris");
+        edit_session(&conn, id, None, None, Some(time("13:30"))).unwrap();
+        assert_eq!(path(id), "factorio.exe");
+        assert!(edit_session(&conn, 99, None, None, None).is_err());
+    }
+
+    #[test]
+    fn test_delete_session() {
+        let conn = setup();
+        let id = add_session(&conn, "factorio", time("10:00"), time("12:00")).unwrap();
+        conn.execute(
+            "INSERT INTO sessions (path, pid, started, game) VALUES ('tetris', 1, ?1, 'tetris')",
+            [time("11:00").to_rfc3339()],
+        )
+        .unwrap();
+        // the daemon's session is left alone
+        assert!(delete_session(&conn, 2).is_err());
+        delete_session(&conn, id).unwrap();
+        let sessions = load_sessions(&conn, &Filter::default()).unwrap();
+        assert_eq!(sessions.len(), 1);
+        let segments: i64 = conn
+            .query_row("SELECT COUNT(*) FROM segments", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(segments, 0);
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fmt, io};
+
+/// Exit codes of the commands, from sysexits(3).
+const EX_DATAERR: i32 = 65;
+const EX_UNAVAILABLE: i32 = 69;
+const EX_IOERR: i32 = 74;
+const EX_CONFIG: i32 = 78;
+
+/// What went wrong, by where it went wrong.
+#[derive(Debug)]
+pub enum Error {
+    /// The config file is missing, unreadable or invalid
+    Config(String),
+    /// A query or write failed
+    Db(rusqlite::Error),
+    /// The database cannot be brought to or used at this binary's schema
+    Schema(String),
+    /// Listing processes or reading their details under /proc failed
+    Proc(io::Error),
+    /// A file or socket operation failed; `what` says which
+    Io { what: String, source: io::Error },
+    /// A request that cannot be carried out as asked, such as a session
+    /// that ends before it starts
+    Invalid(String),
+    /// The daemon is not running or turned a request down
+    Daemon(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn io(what: impl Into<String>, source: io::Error) -> Error {
+        Error::Io {
+            what: what.into(),
+            source,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => EX_CONFIG,
+            Error::Db(_) | Error::Schema(_) | Error::Proc(_) | Error::Io { .. } => EX_IOERR,
+            Error::Invalid(_) => EX_DATAERR,
+            Error::Daemon(_) => EX_UNAVAILABLE,
+        }
+    }
+}
+
+/// Whether another connection holds a lock on the database, so trying
+/// again later may work.
+pub fn is_busy(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(e) => write!(f, "Config error: {}", e),
+            Error::Db(e) => write!(f, "Database error: {}", e),
+            Error::Schema(e) => write!(f, "Cannot use database: {}", e),
+            Error::Proc(e) => write!(f, "Cannot read process information: {}", e),
+            Error::Io { what, source } => write!(f, "Cannot {}: {}", what, source),
+            Error::Invalid(e) | Error::Daemon(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Db(e) => Some(e),
+            Error::Proc(source) | Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Db(e)
+    }
+}
@@ -0,0 +1,344 @@
+use crate::budget::{Budget, duration};
+use crate::log::{debug, warn};
+use crate::tracker::OpenSession;
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    process::{Child, Command, ExitStatus},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A command the daemon runs when something happens to a session.
+///
+/// ```yaml
+/// hooks:
+///     - on: start
+///       command: notify-send "$PLAYTIME_NAME started"
+///     - on: every
+///       minutes: 60
+///       games: [factorio]
+///       command: notify-send "An hour of $PLAYTIME_NAME"
+///     - on: budget_warning
+///       before: 10m
+///       command: notify-send "$PLAYTIME_REMAINING seconds left"
+/// ```
+///
+/// The command runs with `sh -c` and gets `PLAYTIME_EVENT` plus, for
+/// session events, `PLAYTIME_GAME`, `PLAYTIME_NAME`, `PLAYTIME_PID`,
+/// `PLAYTIME_STARTED` and `PLAYTIME_ELAPSED` (seconds since the start),
+/// and for budget warnings `PLAYTIME_GAMES` and `PLAYTIME_REMAINING`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Hook {
+    pub on: HookEvent,
+    pub command: String,
+    /// Only for these game ids; for budget warnings, only budgets covering
+    /// one of them
+    #[serde(default)]
+    pub games: Vec<String>,
+    /// Minutes between runs of an `every` hook
+    pub minutes: Option<u64>,
+    /// How much time a budget has left when its `budget_warning` hook runs
+    #[serde(default, deserialize_with = "duration")]
+    pub before: Option<i64>,
+    /// Seconds before the command is killed
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+/// `before` when a budget warning hook leaves it out.
+const DEFAULT_BEFORE: i64 = 300;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    Start,
+    End,
+    /// Every `minutes` while the game runs
+    Every,
+    /// Once when a budget gets down to `before`
+    BudgetWarning,
+}
+
+impl HookEvent {
+    fn as_str(self) -> &'static str {
+        match self {
+            HookEvent::Start => "start",
+            HookEvent::End => "end",
+            HookEvent::Every => "every",
+            HookEvent::BudgetWarning => "budget_warning",
+        }
+    }
+}
+
+impl Hook {
+    fn covers(&self, game: &str) -> bool {
+        self.games.is_empty() || self.games.iter().any(|id| id == game)
+    }
+
+    fn covers_budget(&self, budget: &Budget) -> bool {
+        self.games.is_empty()
+            || budget.games.is_empty()
+            || budget.games.iter().any(|game| self.covers(game))
+    }
+}
+
+/// Decides when hooks are due and starts them.
+#[derive(Default)]
+pub struct HookRunner {
+    /// (hook, game id) -> `every` periods run for so far in the open session
+    every: HashMap<(usize, String), i64>,
+    /// (hook, budget) pairs that have warned since the budget last had
+    /// more time left
+    warned: HashSet<(usize, usize)>,
+}
+
+impl HookRunner {
+    pub fn started(&mut self, hooks: &[Hook], session: &OpenSession, now: DateTime<Local>) {
+        self.session_event(hooks, HookEvent::Start, session, now);
+    }
+
+    pub fn ended(&mut self, hooks: &[Hook], session: &OpenSession, now: DateTime<Local>) {
+        self.every.retain(|(_, game), _| *game != session.game);
+        self.session_event(hooks, HookEvent::End, session, now);
+    }
+
+    fn session_event(
+        &self,
+        hooks: &[Hook],
+        event: HookEvent,
+        session: &OpenSession,
+        now: DateTime<Local>,
+    ) {
+        for hook in hooks {
+            if hook.on == event && hook.covers(&session.game) {
+                spawn(hook, session_env(event, session, now));
+            }
+        }
+    }
+
+    /// Runs `every` hooks that came due for the open sessions.
+    pub fn tick(&mut self, hooks: &[Hook], sessions: &[OpenSession], now: DateTime<Local>) {
+        for (i, hook) in hooks.iter().enumerate() {
+            for session in sessions {
+                let Some(periods) = periods(hook, session, now) else {
+                    continue;
+                };
+                let done = self.every.entry((i, session.game.clone())).or_insert(0);
+                if periods > *done {
+                    *done = periods;
+                    spawn(hook, session_env(HookEvent::Every, session, now));
+                }
+            }
+        }
+    }
+
+    /// Runs `budget_warning` hooks for budgets that got down to their
+    /// `before`. `remaining` holds the seconds left of each budget whose
+    /// games are running, by index.
+    pub fn budgets(&mut self, hooks: &[Hook], budgets: &[Budget], remaining: &[(usize, i64)]) {
+        for (i, hook) in hooks.iter().enumerate() {
+            if hook.on != HookEvent::BudgetWarning {
+                continue;
+            }
+            let before = hook.before.unwrap_or(DEFAULT_BEFORE);
+            for &(budget, left) in remaining {
+                if !hook.covers_budget(&budgets[budget]) {
+                    continue;
+                }
+                if left > before {
+                    self.warned.remove(&(i, budget));
+                } else if self.warned.insert((i, budget)) {
+                    spawn(
+                        hook,
+                        vec![
+                            (
+                                "PLAYTIME_EVENT",
+                                HookEvent::BudgetWarning.as_str().to_string(),
+                            ),
+                            ("PLAYTIME_GAMES", budgets[budget].games.join(",")),
+                            ("PLAYTIME_REMAINING", left.max(0).to_string()),
+                        ],
+                    );
+                }
+            }
+        }
+    }
+
+    /// Forgets which budgets were warned about, for when the hooks or
+    /// budgets changed.
+    pub fn reset_warnings(&mut self) {
+        self.warned.clear();
+    }
+
+    /// Takes the `every` hooks of a new config as run for the periods the
+    /// open sessions have already been going, so a reload keeps them on
+    /// schedule.
+    pub fn reset_every(&mut self, hooks: &[Hook], sessions: &[OpenSession], now: DateTime<Local>) {
+        self.every.clear();
+        for (i, hook) in hooks.iter().enumerate() {
+            for session in sessions {
+                if let Some(periods) = periods(hook, session, now) {
+                    self.every.insert((i, session.game.clone()), periods);
+                }
+            }
+        }
+    }
+}
+
+/// Whole `minutes` periods `session` has been going for, if `hook` is an
+/// `every` hook for its game.
+fn periods(hook: &Hook, session: &OpenSession, now: DateTime<Local>) -> Option<i64> {
+    let minutes = hook
+        .minutes
+        .filter(|_| hook.on == HookEvent::Every && hook.covers(&session.game))?;
+    Some((now - session.started).num_seconds() / (minutes as i64 * 60))
+}
+
+fn session_env(
+    event: HookEvent,
+    session: &OpenSession,
+    now: DateTime<Local>,
+) -> Vec<(&'static str, String)> {
+    vec![
+        ("PLAYTIME_EVENT", event.as_str().to_string()),
+        ("PLAYTIME_GAME", session.game.clone()),
+        ("PLAYTIME_NAME", session.name.clone()),
+        ("PLAYTIME_PID", session.pid.to_string()),
+        ("PLAYTIME_STARTED", session.started.to_rfc3339()),
+        (
+            "PLAYTIME_ELAPSED",
+            (now - session.started).num_seconds().to_string(),
+        ),
+    ]
+}
+
+fn spawn(hook: &Hook, env: Vec<(&'static str, String)>) {
+    run_in_background(&hook.command, env, hook.timeout);
+}
+
+/// Starts `command` with `sh -c` and waits for it on a thread of its own,
+/// so a slow one does not hold up the daemon. It is started right away so
+/// that it still runs if the daemon exits next. Failures and timeouts are
+/// logged.
+pub fn run_in_background(command: &str, env: Vec<(&'static str, String)>, timeout: u64) {
+    debug!("Running {:?}", command);
+    let child = match start(command, &env) {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Command {:?} failed: {}", command, e);
+            return;
+        }
+    };
+    let command = command.to_string();
+    thread::spawn(move || match wait(child, Duration::from_secs(timeout)) {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("Command {:?} failed: {}", command, status),
+        Err(e) => warn!("Command {:?} failed: {}", command, e),
+    });
+}
+
+fn start(command: &str, env: &[(&str, String)]) -> Result<Child, String> {
+    Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .spawn()
+        .map_err(|e| format!("cannot start: {}", e))
+}
+
+/// Waits for `child`, killing it after `timeout`.
+fn wait(mut child: Child, timeout: Duration) -> Result<ExitStatus, String> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("killed after {} seconds", timeout.as_secs()));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    fn hook(yaml: &str) -> Hook {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_hook_config() {
+        let parsed = hook("{on: budget_warning, before: 10m, command: 'true'}");
+        assert_eq!(parsed.on, HookEvent::BudgetWarning);
+        assert_eq!((parsed.before, parsed.timeout), (Some(600), 30));
+        assert!(serde_yaml::from_str::<Hook>("{on: reboot, command: 'true'}").is_err());
+        assert!(serde_yaml::from_str::<Hook>("{on: start}").is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let env = [("PLAYTIME_GAME", "factorio".to_string())];
+        let run = |command: &str, timeout: Duration| wait(start(command, &env).unwrap(), timeout);
+        let status = run("test \"$PLAYTIME_GAME\" = factorio", Duration::from_secs(5));
+        assert!(status.unwrap().success());
+        assert!(!run("exit 3", Duration::from_secs(5)).unwrap().success());
+
+        let started = Instant::now();
+        assert!(run("sleep 10", Duration::from_millis(200)).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_every_and_warnings() {
+        // `true` so the spawned commands do nothing
+        let hooks = [
+            hook("{on: every, minutes: 30, command: 'true'}"),
+            hook("{on: budget_warning, before: 300, command: 'true'}"),
+        ];
+        let start = Local::now();
+        let session = OpenSession {
+            game: "factorio".to_string(),
+            name: "Factorio".to_string(),
+            pid: 1,
+            started: start,
+            processes: 1,
+            paused: false,
+        };
+        let sessions = [session];
+        let at = |mins: i64| start + TimeDelta::minutes(mins);
+        let mut runner = HookRunner::default();
+        for mins in [0, 29, 31, 45, 95] {
+            runner.tick(&hooks, &sessions, at(mins));
+        }
+        // ran at 31 and 95; the one due at 60 fell between scans and is
+        // not made up for
+        assert_eq!(runner.every[&(0, "factorio".to_string())], 3);
+
+        // after a reload at 100 minutes the next run is still the one at 120
+        let mut reloaded = HookRunner::default();
+        reloaded.reset_every(&hooks, &sessions, at(100));
+        assert_eq!(reloaded.every[&(0, "factorio".to_string())], 3);
+
+        let budgets: Vec<Budget> = vec![serde_yaml::from_str("daily: 1h").unwrap()];
+        runner.budgets(&hooks, &budgets, &[(0, 600)]);
+        assert!(runner.warned.is_empty());
+        runner.budgets(&hooks, &budgets, &[(0, 200)]);
+        runner.budgets(&hooks, &budgets, &[(0, 100)]);
+        assert!(runner.warned.contains(&(1, 0)));
+
+        runner.ended(&hooks, &sessions[0], at(100));
+        assert!(runner.every.is_empty());
+    }
+}
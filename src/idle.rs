@@ -0,0 +1,289 @@
+use chrono::{DateTime, Local, TimeZone};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Read},
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+    ptr,
+};
+
+/// Size of `struct input_event`: a timeval, type, code and value.
+const INPUT_EVENT: usize = size_of::<libc::input_event>();
+const EV_KEY: u16 = 1;
+const EV_REL: u16 = 2;
+const EV_ABS: u16 = 3;
+
+/// The `idle` config section; leaving it out turns idle detection off.
+///
+/// ```yaml
+/// idle:
+///     threshold: 600
+///     source: interrupts
+///     interrupts: [i8042, xhci_hcd]
+/// ```
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdleConfig {
+    /// Seconds without input after which time stops counting as played
+    pub threshold: u64,
+    pub source: IdleSource,
+    /// Names in /proc/interrupts that belong to input devices
+    pub interrupts: Vec<String>,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        IdleConfig {
+            threshold: 300,
+            source: IdleSource::Auto,
+            interrupts: ["i8042", "xhci_hcd", "ehci_hcd", "ohci_hcd", "uhci_hcd"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdleSource {
+    /// Input devices if we may read them, interrupt counts otherwise
+    Auto,
+    /// Event timestamps from /dev/input/event* (needs the `input` group)
+    Input,
+    /// Changes in the /proc/interrupts counts of `interrupts`; coarser,
+    /// since USB controllers also serve devices that are not for input
+    Interrupts,
+}
+
+/// Watches for keyboard, mouse and gamepad activity.
+pub struct IdleDetector {
+    inputs: Inputs,
+    last_input: DateTime<Local>,
+}
+
+enum Inputs {
+    Devices(HashMap<PathBuf, File>),
+    Interrupts { names: Vec<String>, count: u64 },
+}
+
+impl IdleDetector {
+    /// Starts out assuming the user was active just now.
+    pub fn open(config: &IdleConfig, now: DateTime<Local>) -> Result<IdleDetector, String> {
+        let inputs = match config.source {
+            IdleSource::Input => Inputs::devices()?,
+            IdleSource::Interrupts => Inputs::interrupts(&config.interrupts)?,
+            IdleSource::Auto => {
+                Inputs::devices().or_else(|_| Inputs::interrupts(&config.interrupts))?
+            }
+        };
+        Ok(IdleDetector {
+            inputs,
+            last_input: now,
+        })
+    }
+
+    pub fn source(&self) -> &'static str {
+        match self.inputs {
+            Inputs::Devices(_) => "input devices",
+            Inputs::Interrupts { .. } => "/proc/interrupts",
+        }
+    }
+
+    /// When the most recent input happened, as far as we can tell.
+    pub fn last_input(&mut self, now: DateTime<Local>) -> DateTime<Local> {
+        let latest = match &mut self.inputs {
+            Inputs::Devices(devices) => {
+                open_new_devices(devices);
+                let mut latest = None;
+                devices.retain(|_, device| match drain(device) {
+                    Ok(time) => {
+                        latest = latest.max(time);
+                        true
+                    }
+                    // unplugged
+                    Err(_) => false,
+                });
+                // event times use the realtime clock, so they compare with
+                // `now`, but not beyond it
+                latest.map(|time: DateTime<Local>| time.min(now))
+            }
+            Inputs::Interrupts { names, count } => {
+                let current = fs::read_to_string("/proc/interrupts")
+                    .map(|interrupts| count_interrupts(&interrupts, names))
+                    .unwrap_or(*count);
+                let changed = current != *count;
+                *count = current;
+                changed.then_some(now)
+            }
+        };
+        if let Some(time) = latest {
+            self.last_input = self.last_input.max(time);
+        }
+        self.last_input
+    }
+}
+
+impl Inputs {
+    fn devices() -> Result<Inputs, String> {
+        let mut devices = HashMap::new();
+        open_new_devices(&mut devices);
+        if devices.is_empty() {
+            return Err("no readable devices in /dev/input".to_string());
+        }
+        Ok(Inputs::Devices(devices))
+    }
+
+    fn interrupts(names: &[String]) -> Result<Inputs, String> {
+        let interrupts = fs::read_to_string("/proc/interrupts")
+            .map_err(|e| format!("cannot read /proc/interrupts: {}", e))?;
+        if !interrupts
+            .lines()
+            .any(|line| names.iter().any(|name| line.contains(name.as_str())))
+        {
+            return Err(format!(
+                "none of {} found in /proc/interrupts",
+                names.join(", ")
+            ));
+        }
+        Ok(Inputs::Interrupts {
+            names: names.to_vec(),
+            count: count_interrupts(&interrupts, names),
+        })
+    }
+}
+
+/// Opens event devices that appeared since the last call, such as a gamepad
+/// that was just plugged in.
+fn open_new_devices(devices: &mut HashMap<PathBuf, File>) {
+    let Ok(entries) = fs::read_dir("/dev/input") else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_event = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("event"));
+        if !is_event || devices.contains_key(&path) {
+            continue;
+        }
+        let device = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path);
+        if let Ok(device) = device {
+            devices.insert(path, device);
+        }
+    }
+}
+
+/// Reads all pending events, returning the time of the latest key, button,
+/// pointer or stick event.
+fn drain(device: &mut File) -> io::Result<Option<DateTime<Local>>> {
+    let mut buf = [0u8; INPUT_EVENT * 64];
+    let mut latest = None;
+    loop {
+        match device.read(&mut buf) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => latest = latest.max(latest_event(&buf[..n])),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(latest),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// time_t and suseconds_t are not i64 on every target
+#[allow(clippy::unnecessary_cast)]
+fn latest_event(events: &[u8]) -> Option<DateTime<Local>> {
+    events
+        .chunks_exact(INPUT_EVENT)
+        // SAFETY: each chunk is exactly one input_event, which is plain
+        // integers so any bytes make a valid one, and read_unaligned does
+        // not need the buffer aligned for it
+        .map(|event| unsafe { ptr::read_unaligned(event.as_ptr().cast::<libc::input_event>()) })
+        .filter(|event| matches!(event.type_, EV_KEY | EV_REL | EV_ABS))
+        .filter_map(|event| {
+            let secs = event.time.tv_sec as i64;
+            let micros = event.time.tv_usec as i64;
+            Local
+                .timestamp_opt(secs, (micros * 1000).clamp(0, 999_999_999) as u32)
+                .single()
+        })
+        .max()
+}
+
+/// Total count, over all CPUs, of the interrupt lines naming one of `names`.
+fn count_interrupts(interrupts: &str, names: &[String]) -> u64 {
+    interrupts
+        .lines()
+        .filter(|line| names.iter().any(|name| line.contains(name.as_str())))
+        .map(|line| {
+            line.split_whitespace()
+                .skip(1)
+                .map_while(|count| count.parse::<u64>().ok())
+                .sum::<u64>()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_interrupts() {
+        let interrupts = "           CPU0       CPU1
+  1:        120         30   IO-APIC    1-edge      i8042
+ 12:       4000          0   IO-APIC   12-edge      i8042
+ 16:          5          7   IO-APIC   16-fasteoi   xhci_hcd, snd_hda_intel
+ 24:      99999          1   PCI-MSI 327680-edge   nvme0q0
+LOC:     118020     118000   Local timer interrupts
+";
+        let names = ["i8042".to_string(), "xhci_hcd".to_string()];
+        assert_eq!(
+            count_interrupts(interrupts, &names),
+            120 + 30 + 4000 + 5 + 7
+        );
+        assert_eq!(count_interrupts(interrupts, &[]), 0);
+    }
+
+    #[test]
+    fn test_latest_event() {
+        let event = |secs: i64, kind: u16| {
+            let event = libc::input_event {
+                time: libc::timeval {
+                    tv_sec: secs as _,
+                    tv_usec: 500_000,
+                },
+                type_: kind,
+                code: 0,
+                value: 1,
+            };
+            // SAFETY: event is plain integers, INPUT_EVENT bytes long
+            unsafe {
+                std::slice::from_raw_parts(
+                    (&event as *const libc::input_event).cast::<u8>(),
+                    INPUT_EVENT,
+                )
+            }
+            .to_vec()
+        };
+        let mut events = event(1_750_000_000, EV_KEY);
+        // a later sync event does not count as input
+        events.extend(event(1_750_000_100, 0));
+        events.extend(event(1_750_000_050, EV_REL));
+        let latest = latest_event(&events).unwrap();
+        assert_eq!(latest.timestamp_millis(), 1_750_000_050_500);
+        assert!(latest_event(&event(1_750_000_000, 0)).is_none());
+    }
+
+    #[test]
+    fn test_idle_config() {
+        let config: IdleConfig = serde_yaml::from_str("threshold: 60").unwrap();
+        assert_eq!(config.threshold, 60);
+        assert_eq!(config.source, IdleSource::Auto);
+        assert!(serde_yaml::from_str::<IdleConfig>("source: camera").is_err());
+    }
+}
@@ -0,0 +1,355 @@
+use chrono::{Local, SecondsFormat};
+use serde::Deserialize;
+use std::{
+    env, fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Mutex, Once},
+};
+
+/// The `log` config section. Without a `file`, lines go to stderr; under
+/// systemd they carry the journal's priority prefixes instead of
+/// timestamps.
+///
+/// ```yaml
+/// log:
+///     level: debug
+///     format: json
+///     file: /var/log/playtime.log
+///     max_size: 10485760
+///     keep: 3
+/// ```
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// The least severe level that is written
+    pub level: Level,
+    pub format: LogFormat,
+    pub file: Option<PathBuf>,
+    /// Bytes the file may grow to before it is rotated
+    pub max_size: u64,
+    /// Rotated files kept next to it, as `FILE.1` (newest) to `FILE.N`
+    pub keep: u32,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: Level::Info,
+            format: LogFormat::Text,
+            file: None,
+            max_size: 10 * 1024 * 1024,
+            keep: 3,
+        }
+    }
+}
+
+/// Most severe first.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    /// The syslog priority, as understood by journald on stderr.
+    fn priority(self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug => 7,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// `TIME LEVEL message`
+    Text,
+    /// One object per line with `time`, `level`, `module` and `message`
+    Json,
+}
+
+struct Logger {
+    config: LogConfig,
+    /// open when `config.file` is set
+    file: Option<File>,
+    /// bytes in `file`
+    size: u64,
+    /// stderr is connected to the journal
+    journal: bool,
+}
+
+/// `None` until `init`, which the daemon calls; until then, and for the
+/// other commands, info and up go to stderr as text.
+static LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+
+/// (Re)configures logging. If the file cannot be opened, lines go to
+/// stderr and the error is returned.
+pub fn init(config: &LogConfig) -> Result<(), String> {
+    let mut config = config.clone();
+    let opened = config.file.as_deref().map(|path| {
+        open(path).map_err(|e| format!("cannot open log file {}: {}", path.display(), e))
+    });
+    let (file, result) = match opened {
+        Some(Ok(file)) => (Some(file), Ok(())),
+        Some(Err(e)) => {
+            config.file = None;
+            (None, Err(e))
+        }
+        None => (None, Ok(())),
+    };
+    let size = file
+        .as_ref()
+        .and_then(|file| file.metadata().ok())
+        .map_or(0, |meta| meta.len());
+    *LOGGER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Logger {
+        config,
+        file,
+        size,
+        journal: on_journal(),
+    });
+    static PANICS: Once = Once::new();
+    PANICS.call_once(|| {
+        // so a crash ends up in the log file too; stderr gets it anyway.
+        // Not if the panic came from inside the logger, which holds the lock.
+        let default = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if let Ok(mut logger) = LOGGER.try_lock()
+                && let Some(logger) = logger.as_mut().filter(|logger| logger.file.is_some())
+            {
+                let line = format_line(
+                    logger.config.format,
+                    Level::Error,
+                    "panic",
+                    &info.to_string(),
+                );
+                let _ = logger.write(&line);
+            }
+            default(info);
+        }));
+    });
+    result
+}
+
+/// Whether stderr is the stream systemd gave us to the journal, going by
+/// `JOURNAL_STREAM` (`DEVICE:INODE`).
+fn on_journal() -> bool {
+    let Ok(stream) = env::var("JOURNAL_STREAM") else {
+        return false;
+    };
+    let Some((dev, ino)) = stream
+        .split_once(':')
+        .and_then(|(dev, ino)| Some((dev.parse::<u64>().ok()?, ino.parse::<u64>().ok()?)))
+    else {
+        return false;
+    };
+    fs::metadata("/proc/self/fd/2").is_ok_and(|meta| meta.dev() == dev && meta.ino() == ino)
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Writes one line; use the macros instead.
+pub fn log(level: Level, module: &str, args: fmt::Arguments) {
+    // `module_path!()` within this crate
+    let module = module.strip_prefix("playtime::").unwrap_or(module);
+    let mut logger = LOGGER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let Some(logger) = logger.as_mut() else {
+        if level <= Level::Info {
+            eprintln!(
+                "{}",
+                format_line(LogFormat::Text, level, module, &args.to_string())
+            );
+        }
+        return;
+    };
+    if level > logger.config.level {
+        return;
+    }
+    let message = args.to_string();
+    if logger.file.is_none() && logger.journal {
+        let line = match logger.config.format {
+            LogFormat::Text => message,
+            LogFormat::Json => json_line(level, module, &message),
+        };
+        eprintln!("<{}>{}", level.priority(), line);
+        return;
+    }
+    let line = format_line(logger.config.format, level, module, &message);
+    if logger.file.is_some() {
+        if let Err(e) = logger.write(&line) {
+            eprintln!("Cannot write to the log file: {}", e);
+            eprintln!("{}", line);
+        }
+    } else {
+        eprintln!("{}", line);
+    }
+}
+
+fn format_line(format: LogFormat, level: Level, module: &str, message: &str) -> String {
+    match format {
+        LogFormat::Text => format!(
+            "{} {:5} {}",
+            Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            level.as_str().to_uppercase(),
+            message
+        ),
+        LogFormat::Json => json_line(level, module, message),
+    }
+}
+
+fn json_line(level: Level, module: &str, message: &str) -> String {
+    serde_json::json!({
+        "time": Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+        "level": level.as_str(),
+        "module": module,
+        "message": message,
+    })
+    .to_string()
+}
+
+impl Logger {
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.config.max_size {
+            self.rotate()?;
+        }
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+        writeln!(file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Moves `FILE` to `FILE.1`, `FILE.1` to `FILE.2` and so on, dropping
+    /// the oldest, and starts a new `FILE`.
+    fn rotate(&mut self) -> io::Result<()> {
+        let Some(path) = self.config.file.clone() else {
+            return Ok(());
+        };
+        let numbered = |n: u32| {
+            let mut name = path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.config.keep == 0 {
+            fs::remove_file(&path)?;
+        } else {
+            let _ = fs::remove_file(numbered(self.config.keep));
+            for n in (1..self.config.keep).rev() {
+                let _ = fs::rename(numbered(n), numbered(n + 1));
+            }
+            fs::rename(&path, numbered(1))?;
+        }
+        self.file = Some(open(&path)?);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Error, module_path!(), format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Warn, module_path!(), format_args!($($arg)*))
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Info, module_path!(), format_args!($($arg)*))
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Debug, module_path!(), format_args!($($arg)*))
+    };
+}
+
+// `warn` itself would clash with the built-in attribute
+pub(crate) use {debug, error, info, log_warn as warn};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_config() {
+        let config: LogConfig = serde_yaml::from_str("{level: warn, format: json}").unwrap();
+        assert_eq!(
+            (config.level, config.format),
+            (Level::Warn, LogFormat::Json)
+        );
+        assert_eq!(config.keep, 3);
+        assert!(Level::Error < Level::Warn && Level::Info < Level::Debug);
+        assert!(serde_yaml::from_str::<LogConfig>("level: loud").is_err());
+    }
+
+    #[test]
+    fn test_format_line() {
+        let line = format_line(LogFormat::Json, Level::Warn, "tracker", "a \"quoted\" game");
+        let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed["level"], "warn");
+        assert_eq!(parsed["module"], "tracker");
+        assert_eq!(parsed["message"], "a \"quoted\" game");
+        let line = format_line(LogFormat::Text, Level::Info, "tracker", "Started");
+        assert!(line.ends_with(" INFO  Started"));
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = std::env::temp_dir().join(format!("playtime-log-{}", std::process::id()));
+        let path = dir.join("playtime.log");
+        let mut logger = Logger {
+            config: LogConfig {
+                file: Some(path.clone()),
+                max_size: 20,
+                keep: 2,
+                ..LogConfig::default()
+            },
+            file: Some(open(&path).unwrap()),
+            size: 0,
+            journal: false,
+        };
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            logger.write(line).unwrap();
+        }
+        let read = |suffix: &str| fs::read_to_string(format!("{}{}", path.display(), suffix));
+        assert_eq!(read("").unwrap(), "fourth line\n");
+        assert_eq!(read(".1").unwrap(), "third line\n");
+        assert_eq!(read(".2").unwrap(), "second line\n");
+        assert!(read(".3").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
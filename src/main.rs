@@ -1,166 +1,206 @@
-use chrono::{DateTime, Local};
-use rusqlite::{Connection, params};
-use serde::Deserialize;
-use std::{collections::HashMap, env, fs, path::PathBuf, thread, time::Duration};
-// use std::os::unix::fs::PermissionsExt;
-#[derive(Deserialize)]
-struct Config {
-    tracked: Vec<String>,
-}
-
-fn load_config() -> Config {
-    let mut path = dirs::config_dir().expect("no config dir");
-    path.push("playtime-tracker/config.yaml");
-
-    if !path.exists() {
-        fs::create_dir_all(path.parent().unwrap()).expect("failed to create config dir");
-        let default_config = r#"
-tracked:
-    - "example_program"
-"#;
-        fs::write(&path, default_config).expect("failed to create config file");
-        println!("Created default config at {:?}", path);
-        std::process::exit(0);
+mod budget;
+mod cli;
+mod config;
+mod control;
+mod daemon;
+mod db;
+mod edit;
+mod error;
+mod hooks;
+mod idle;
+mod log;
+mod matcher;
+mod netlink;
+mod notify;
+mod output;
+mod period;
+mod procfs;
+mod report;
+mod signals;
+mod source;
+mod suspend;
+mod tracker;
+mod watch;
+
+use chrono::Local;
+use clap::Parser;
+use cli::{Cli, Command, ConfigCommand, DbCommand, SessionCommand};
+use config::{load_config, read_config};
+use control::Request;
+use daemon::Daemon;
+use db::{init_db, sync_games};
+use error::{Error, Result};
+use log::error;
+use rusqlite::Connection;
+use std::path::Path;
+
+/// Opens the database for the reporting and editing commands. Unlike the
+/// daemon they work without a usable config; if there is one, display
+/// names are refreshed from it.
+fn open_for_reading(config_path: &Path, db_path: &Path) -> Result<Connection> {
+    let conn = init_db(db_path)?;
+    if let Ok(config) = read_config(config_path) {
+        sync_games(&config, &conn)?;
     }
+    Ok(conn)
+}
 
-    let config_str = fs::read_to_string(&path).expect("failed to read config file");
-    let config: Config = serde_yaml::from_str(&config_str).expect("failed to parse config");
-
-    if config.tracked.is_empty() {
-        println!("No programs to track in config. Please add them.");
-        std::process::exit(0);
+fn config_command(command: ConfigCommand, path: &Path) -> Result<()> {
+    match command {
+        ConfigCommand::Path => println!("{}", path.display()),
+        ConfigCommand::Check => {
+            let config = read_config(path)?;
+            for game in &config.games {
+                let rules: Vec<String> = game.matchers.iter().map(|m| m.to_string()).collect();
+                println!("- {} ({}): {}", game.id, game.name, rules.join(", "));
+            }
+        }
     }
-    config
+    Ok(())
 }
 
-fn init_db() -> Connection {
-    let mut db_path = dirs::data_local_dir().expect("no data dir");
-    db_path.push("playtime-tracker");
-    db_path.set_extension("sqlite");
-    
-    if !db_path.exists() {
-        fs::create_dir_all(db_path.parent().unwrap()).expect("failed to create data dir");
-        println!("Created data dir at {:?}", db_path);
+fn db_command(command: DbCommand, path: &Path) -> Result<()> {
+    match command {
+        DbCommand::Path => println!("{}", path.display()),
+        DbCommand::Info => {
+            let conn = init_db(path)?;
+            let count =
+                |sql: &str| -> Result<i64> { Ok(conn.query_row(sql, [], |row| row.get(0))?) };
+            println!("Database: {}", path.display());
+            println!("Schema version: {}", db::schema_version(&conn)?);
+            println!("Games: {}", count("SELECT COUNT(*) FROM games")?);
+            println!("Sessions: {}", count("SELECT COUNT(*) FROM sessions")?);
+            println!(
+                "Open sessions: {}",
+                count("SELECT COUNT(*) FROM sessions WHERE ended IS NULL")?
+            );
+        }
+        DbCommand::Backup { file } => {
+            let conn = init_db(path)?;
+            conn.execute("VACUUM INTO ?1", [file.to_string_lossy()])?;
+            println!("Backed up {} to {}", path.display(), file.display());
+        }
     }
-
-    let conn = Connection::open(db_path).expect("failed to open db");
-    conn.execute_batch(
-        "CREATE TABLE IF NOT EXISTS sessions (
-            id       INTEGER PRIMARY KEY,
-            path     TEXT NOT NULL,
-            pid      INTEGER NOT NULL,
-            started  TEXT NOT NULL,
-            ended    TEXT
-        );",
-    )
-    .unwrap();
-
-    conn
+    Ok(())
 }
 
-fn run_daemon(config: &Config, conn: &Connection) {
-    // map pid -> (binary name, start timestamp)
-    let mut active: HashMap<i32, (String, DateTime<Local>)> = HashMap::new();
-    loop {
-        let mut seen_pids = Vec::new();
-        for entry in fs::read_dir("/proc").unwrap() {
-            if let Ok(ent) = entry {
-                if let Ok(pid) = ent.file_name().to_string_lossy().parse::<i32>() {
-                    let mut comm = PathBuf::from("/proc");
-                    comm.push(pid.to_string());
-                    comm.push("comm");
-                    if let Ok(name) = fs::read_to_string(&comm) {
-                        let name = name.trim().to_string();
-                        if config.tracked.contains(&name) {
-                            seen_pids.push((pid, name));
-                        }
-                    }
-                }
-            }
+fn session_command(command: SessionCommand, conn: &Connection) -> Result<()> {
+    let done = match command {
+        SessionCommand::Add { game, start, end } => {
+            let id = edit::add_session(conn, &game, start, end)?;
+            format!("Added session {}.", id)
         }
-
-        // detect new
-        for (pid, name) in &seen_pids {
-            if !active.contains_key(pid) {
-                let now = Local::now();
-                conn.execute(
-                    "INSERT INTO sessions (path, pid, started) VALUES (?1, ?2, ?3)",
-                    params![name, pid, now.to_rfc3339()],
-                )
-                .unwrap();
-                active.insert(*pid, (name.clone(), now));
-                println!("Started {} (pid {}) at {}", name, pid, now);
-            }
+        SessionCommand::Edit {
+            id,
+            game,
+            start,
+            end,
+        } => {
+            edit::edit_session(conn, id, game.as_deref(), start, end)?;
+            format!("Updated session {}.", id)
         }
-        // detect ended
-        let prev_pids: Vec<i32> = active.keys().cloned().collect();
-        for pid in prev_pids {
-            if !seen_pids.iter().any(|(p, _)| *p == pid) {
-                if let Some((name, start)) = active.remove(&pid) {
-                    let now = Local::now();
-                    conn.execute(
-                        "UPDATE sessions SET ended = ?1 WHERE pid = ?2 AND ended IS NULL",
-                        params![now.to_rfc3339(), pid],
-                    )
-                    .unwrap();
-                    println!("Ended {} (pid {}) at {}", name, pid, now);
-                }
-            }
+        SessionCommand::Delete { id } => {
+            edit::delete_session(conn, id)?;
+            format!("Deleted session {}.", id)
         }
+    };
+    println!("{}", done);
+    Ok(())
+}
 
-        thread::sleep(Duration::from_secs(1));
+/// Sends a command that only makes sense with a daemon running.
+fn control_command(socket: &Path, request: Request, done: &str) -> Result<()> {
+    match control::send(socket, &request) {
+        Ok(response) if response.ok => {
+            println!("{}", done);
+            Ok(())
+        }
+        Ok(response) => Err(Error::Daemon(response.error.unwrap_or_default())),
+        Err(e) if control::not_running(&e) => Err(Error::Daemon(format!(
+            "No daemon is running (no socket at {})",
+            socket.display()
+        ))),
+        Err(e) => Err(Error::Daemon(format!("Cannot talk to the daemon: {}", e))),
     }
 }
 
-fn report(conn: &Connection) {
-    let mut stmt = conn
-        .prepare(
-            "SELECT path, SUM(
-            strftime('%s', ended) - strftime('%s', started)
-        ) AS total_secs
-        FROM sessions
-        WHERE ended IS NOT NULL
-        GROUP BY path;",
-        )
-        .unwrap();
-
-    let mut rows = stmt.query([]).unwrap();
-    println!("Playtime report:");
-    while let Ok(Some(row)) = rows.next() {
-        let path: String = row.get(0).unwrap();
-        let secs: i64 = row.get(1).unwrap();
-        let h = secs / 3600;
-        let m = (secs % 3600) / 60;
-        let s = secs % 60;
-        println!("- {}: {}h {}m {}s", path, h, m, s);
+fn run(cli: Cli) -> Result<()> {
+    let config_path = match cli.config {
+        Some(path) => path,
+        None => config::default_path()?,
+    };
+    let db_path = match cli.db {
+        Some(path) => path,
+        None => db::default_path()?,
+    };
+    let socket = cli.socket.unwrap_or_else(control::default_path);
+
+    match cli.command.unwrap_or(Command::Daemon) {
+        Command::Daemon => {
+            let config = load_config(&config_path)?;
+            let conn = init_db(&db_path)?;
+            sync_games(&config, &conn)?;
+            Daemon::new(&config_path, config, conn).run(&socket)
+        }
+        Command::Report {
+            filter,
+            sort,
+            by,
+            format,
+        } => {
+            let conn = open_for_reading(&config_path, &db_path)?;
+            report::report(&conn, &filter, sort, by, format)
+        }
+        Command::Sessions {
+            filter,
+            limit,
+            format,
+        } => {
+            let conn = open_for_reading(&config_path, &db_path)?;
+            report::sessions(&conn, &filter, limit, format)
+        }
+        Command::Status => match control::send(&socket, &Request::Status) {
+            Ok(control::Response {
+                status: Some(status),
+                ..
+            }) => {
+                report::live_status(&status, Local::now());
+                Ok(())
+            }
+            result => {
+                if let Err(e) = result.as_ref()
+                    && !control::not_running(e)
+                {
+                    eprintln!("Cannot talk to the daemon, reading the database: {}", e);
+                }
+                let conn = open_for_reading(&config_path, &db_path)?;
+                report::status(&conn, Local::now())
+            }
+        },
+        Command::Reload => control_command(&socket, Request::ReloadConfig, "Config reloaded."),
+        Command::Pause { game } => control_command(&socket, Request::Pause { game }, "Paused."),
+        Command::Resume { game } => control_command(&socket, Request::Resume { game }, "Resumed."),
+        Command::Flush => control_command(&socket, Request::Flush, "Flushed."),
+        Command::Stop => control_command(&socket, Request::Shutdown, "Daemon stopped."),
+        Command::Session { command } => {
+            let conn = open_for_reading(&config_path, &db_path)?;
+            session_command(command, &conn)
+        }
+        Command::Config { command } => config_command(command, &config_path),
+        Command::Db { command } => db_command(command, &db_path),
     }
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let config = load_config();
-    let conn = init_db();
-
-    if args.len() > 1 && args[1] == "report" {
-        report(&conn);
-    } else {
-        run_daemon(&config, &conn);
+    let cli = Cli::parse();
+    let daemon = matches!(cli.command, None | Some(Command::Daemon));
+    if let Err(e) = run(cli) {
+        // the daemon's log may be a file or the journal
+        if daemon {
+            error!("{}", e);
+        } else {
+            eprintln!("{}", e);
+        }
+        std::process::exit(e.exit_code());
     }
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_load_config() {
-        let config = load_config();
-        assert!(!config.tracked.is_empty());
-    }
-
-    #[test]
-    fn test_init_db() {
-        let conn = init_db();
-        assert!(conn.execute("SELECT 1", []).is_ok());
-    }
-}
\ No newline at end of file
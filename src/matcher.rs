@@ -0,0 +1,152 @@
+use crate::procfs::Process;
+use glob::Pattern;
+use regex::Regex;
+use serde::Deserialize;
+use std::{fmt, path::PathBuf};
+
+/// Longest name the kernel keeps in /proc/<pid>/comm (TASK_COMM_LEN - 1).
+const COMM_LEN: usize = 15;
+
+/// A rule deciding whether a process belongs to a tracked program.
+///
+/// In the config a plain string is shorthand for `comm`:
+///
+/// ```yaml
+/// tracked:
+///     - "factorio"
+///     - comm: "factorio"
+///     - exe: "/usr/bin/factorio"
+///     - exe_glob: "**/HollowKnight*"
+///     - cmdline: "(?i)hollow_?knight\\.exe"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawMatcher")]
+pub enum Matcher {
+    Comm(String),
+    Exe(PathBuf),
+    ExeGlob(Pattern),
+    Cmdline(Regex),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawMatcher {
+    Name(String),
+    Rule(RawRule),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum RawRule {
+    Comm(String),
+    Exe(PathBuf),
+    ExeGlob(String),
+    Cmdline(String),
+}
+
+impl TryFrom<RawMatcher> for Matcher {
+    type Error = String;
+
+    fn try_from(raw: RawMatcher) -> Result<Self, Self::Error> {
+        Ok(match raw {
+            RawMatcher::Name(name) | RawMatcher::Rule(RawRule::Comm(name)) => Matcher::Comm(name),
+            RawMatcher::Rule(RawRule::Exe(path)) => Matcher::Exe(path),
+            RawMatcher::Rule(RawRule::ExeGlob(glob)) => Matcher::ExeGlob(
+                Pattern::new(&glob).map_err(|e| format!("invalid exe_glob {:?}: {}", glob, e))?,
+            ),
+            RawMatcher::Rule(RawRule::Cmdline(re)) => Matcher::Cmdline(
+                Regex::new(&re).map_err(|e| format!("invalid cmdline regex {:?}: {}", re, e))?,
+            ),
+        })
+    }
+}
+
+impl Matcher {
+    pub fn matches(&self, proc: &Process) -> bool {
+        match self {
+            // comm is truncated by the kernel, so long names are compared
+            // against their first 15 bytes.
+            Matcher::Comm(name) => match name.get(..COMM_LEN) {
+                Some(prefix) if name.len() > COMM_LEN => proc.comm == prefix,
+                _ => proc.comm == *name,
+            },
+            Matcher::Exe(path) => proc.exe() == Some(path.as_path()),
+            Matcher::ExeGlob(pattern) => proc.exe().is_some_and(|exe| pattern.matches_path(exe)),
+            Matcher::Cmdline(re) => proc.cmdline().is_some_and(|cmd| re.is_match(cmd)),
+        }
+    }
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Matcher::Comm(name) => write!(f, "comm:{}", name),
+            Matcher::Exe(path) => write!(f, "exe:{}", path.display()),
+            Matcher::ExeGlob(pattern) => write!(f, "exe_glob:{}", pattern),
+            Matcher::Cmdline(re) => write!(f, "cmdline:{}", re),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Vec<Matcher> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let rules = parse(
+            r#"
+- "factorio"
+- comm: "hl2_linux"
+- exe: "/usr/bin/factorio"
+- exe_glob: "**/HollowKnight*"
+- cmdline: "(?i)hollow_?knight\\.exe"
+"#,
+        );
+        let shown: Vec<String> = rules.iter().map(|r| r.to_string()).collect();
+        assert_eq!(
+            shown,
+            [
+                "comm:factorio",
+                "comm:hl2_linux",
+                "exe:/usr/bin/factorio",
+                "exe_glob:**/HollowKnight*",
+                "cmdline:(?i)hollow_?knight\\.exe",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(serde_yaml::from_str::<Vec<Matcher>>("- cmdline: \"(\"").is_err());
+        assert!(serde_yaml::from_str::<Vec<Matcher>>("- exe_glob: \"[\"").is_err());
+        assert!(serde_yaml::from_str::<Vec<Matcher>>("- pid: 1").is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let proton = Process::fake(
+            10,
+            1,
+            "HollowKnight.ex",
+            Some("/usr/bin/wine64-preloader"),
+            Some("Z:\\games\\Hollow Knight\\HollowKnight.exe"),
+        );
+        let rules = parse(
+            r#"
+- "HollowKnight.exe"
+- cmdline: "HollowKnight\\.exe$"
+- exe_glob: "/usr/bin/wine*"
+- exe: "/usr/bin/wine64-preloader"
+"#,
+        );
+        assert!(rules.iter().all(|r| r.matches(&proton)));
+
+        let other = Process::fake(11, 1, "bash", Some("/usr/bin/bash"), Some("bash"));
+        assert!(!rules.iter().any(|r| r.matches(&other)));
+    }
+}
@@ -0,0 +1,392 @@
+use crate::control::{Pending, Request};
+use crate::error::{Error, Result};
+use crate::log::error;
+use crate::matcher::Matcher;
+use crate::procfs::{self, Process, ProcessId};
+use crate::source::ProcessSource;
+use std::{
+    collections::{HashMap, HashSet},
+    io, mem,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// from <linux/connector.h> and <linux/cn_proc.h>
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+const PROC_EVENT_FORK: u32 = 0x1;
+const PROC_EVENT_EXEC: u32 = 0x2;
+const PROC_EVENT_COMM: u32 = 0x200;
+const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+
+const NLMSG_HDRLEN: usize = 16;
+const CN_MSG_LEN: usize = 20;
+/// Offset of `event_data` in `struct proc_event`, after `what`, `cpu` and
+/// `timestamp_ns`.
+const EVENT_DATA: usize = 16;
+
+/// What we act on; the pids are thread group ids, i.e. processes.
+#[derive(Debug, PartialEq)]
+enum Event {
+    Fork(i32),
+    Exec(i32),
+    /// renamed itself with prctl(PR_SET_NAME)
+    Comm(i32),
+    Exit(i32),
+}
+
+/// Processes as last reported by the kernel.
+#[derive(Default)]
+struct Table {
+    procs: HashMap<i32, Process>,
+    /// Read since the last snapshot, so not returned by one yet
+    unseen: HashSet<i32>,
+    /// Exited since the last snapshot before being returned by one, with
+    /// their start time so a reused pid is not dropped along with them.
+    exited: Vec<(i32, u64)>,
+    /// Events were lost, so the table has to be rebuilt from /proc.
+    stale: bool,
+    /// Rules of the configured games
+    rules: Vec<Matcher>,
+    /// Pids in open sessions
+    tracked: HashSet<i32>,
+    /// Least time between wakeups for events that are neither
+    interval: Duration,
+}
+
+impl Table {
+    /// Returns whether the event concerns a game, running or about to.
+    fn apply(&mut self, event: Event) -> bool {
+        match event {
+            Event::Fork(pid) => match Process::read(pid) {
+                Some(proc) => {
+                    let relevant = self.tracked.contains(&proc.ppid) || self.is_game(&proc);
+                    self.procs.insert(pid, proc);
+                    self.unseen.insert(pid);
+                    relevant
+                }
+                None => false,
+            },
+            Event::Exec(pid) | Event::Comm(pid) => match Process::read(pid) {
+                Some(proc) => {
+                    // read now: the process may be gone by the next scan
+                    proc.exe();
+                    proc.cmdline();
+                    let relevant = self.tracked.contains(&pid) || self.is_game(&proc);
+                    self.procs.insert(pid, proc);
+                    self.unseen.insert(pid);
+                    relevant
+                }
+                None => false,
+            },
+            Event::Exit(pid) => {
+                if self.unseen.contains(&pid) {
+                    // kept for one snapshot, so short-lived ones are seen
+                    if let Some(proc) = self.procs.get(&pid) {
+                        self.exited.push((pid, proc.starttime));
+                    }
+                } else {
+                    self.procs.remove(&pid);
+                }
+                self.tracked.contains(&pid)
+            }
+        }
+    }
+
+    fn is_game(&self, proc: &Process) -> bool {
+        self.rules.iter().any(|rule| rule.matches(proc))
+    }
+
+    fn snapshot(&mut self) -> io::Result<Vec<Process>> {
+        if self.stale {
+            self.rescan()?;
+        }
+        let snapshot = self.procs.values().cloned().collect();
+        self.unseen.clear();
+        for (pid, starttime) in self.exited.drain(..) {
+            if self
+                .procs
+                .get(&pid)
+                .is_some_and(|proc| proc.starttime == starttime)
+            {
+                self.procs.remove(&pid);
+            }
+        }
+        Ok(snapshot)
+    }
+
+    fn rescan(&mut self) -> io::Result<()> {
+        self.procs = procfs::pids()?
+            .into_iter()
+            .filter_map(|pid| Some((pid, Process::read(pid)?)))
+            .collect();
+        self.unseen.clear();
+        self.exited.clear();
+        self.stale = false;
+        Ok(())
+    }
+}
+
+/// Process events from the kernel's proc connector, collected by a
+/// background thread into a table of running processes.
+pub struct ProcConnector {
+    table: Arc<Mutex<Table>>,
+    /// a wakeup has been sent and not yet answered by `processes`
+    pending: Arc<AtomicBool>,
+}
+
+impl ProcConnector {
+    pub fn open(wake: Sender<Pending>) -> io::Result<ProcConnector> {
+        let fd = subscribe()?;
+        let table = Arc::new(Mutex::new(Table::default()));
+        // after subscribing, so nothing falls between the scan and the
+        // first event
+        lock(&table).rescan()?;
+        let pending = Arc::new(AtomicBool::new(false));
+
+        let (shared, woken) = (table.clone(), pending.clone());
+        thread::spawn(move || {
+            let mut buf = vec![0u8; 8192];
+            let mut last_wake: Option<Instant> = None;
+            loop {
+                // SAFETY: buf is valid for writes of buf.len() bytes, and fd
+                // stays open until this thread closes it below
+                let n = unsafe { libc::recv(fd, buf.as_mut_ptr().cast(), buf.len(), 0) };
+                let (relevant, interval) = if n < 0 {
+                    let e = io::Error::last_os_error();
+                    match e.raw_os_error() {
+                        Some(libc::EINTR) => continue,
+                        Some(libc::ENOBUFS) => {
+                            let mut table = lock(&shared);
+                            table.stale = true;
+                            (true, table.interval)
+                        }
+                        _ => {
+                            error!("Lost the netlink proc connector: {}", e);
+                            // every snapshot from now on rescans /proc
+                            break;
+                        }
+                    }
+                } else {
+                    let mut table = lock(&shared);
+                    let mut relevant = false;
+                    for event in parse(&buf[..n as usize]) {
+                        relevant |= table.apply(event);
+                    }
+                    (relevant, table.interval)
+                };
+                // the rest of the system comes and goes all the time; a
+                // scan for each of it would cost more than polling
+                if !relevant && last_wake.is_some_and(|last| last.elapsed() < interval) {
+                    continue;
+                }
+                last_wake = Some(Instant::now());
+                if !woken.swap(true, Ordering::SeqCst)
+                    && wake.send((Request::Scan, mpsc::channel().0)).is_err()
+                {
+                    break;
+                }
+            }
+            lock(&shared).stale = true;
+            // SAFETY: fd is ours and not used after this
+            unsafe { libc::close(fd) };
+        });
+        Ok(ProcConnector { table, pending })
+    }
+}
+
+impl ProcessSource for ProcConnector {
+    fn processes(&mut self) -> Result<Vec<Process>> {
+        self.pending.store(false, Ordering::SeqCst);
+        let mut table = lock(&self.table);
+        let snapshot = table.snapshot();
+        if Arc::strong_count(&self.table) == 1 {
+            // the reader thread is gone
+            table.stale = true;
+        }
+        snapshot.map_err(Error::Proc)
+    }
+
+    fn watch(&mut self, rules: &[Matcher], tracked: &[ProcessId], interval: Duration) {
+        let mut table = lock(&self.table);
+        table.rules = rules.to_vec();
+        table.tracked = tracked.iter().map(|id| id.pid).collect();
+        table.interval = interval;
+    }
+}
+
+/// The table, even if a panic on the other side poisoned the lock, so one
+/// bad event does not take the daemon down with it.
+fn lock(table: &Mutex<Table>) -> MutexGuard<'_, Table> {
+    table
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Opens a netlink socket and asks for proc events. Fails without
+/// CAP_NET_ADMIN or on kernels built without the connector.
+fn subscribe() -> io::Result<i32> {
+    // SAFETY: plain syscall with constant arguments
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_CONNECTOR,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fail = |fd: i32| {
+        let e = io::Error::last_os_error();
+        // SAFETY: fd is the socket opened above, not used after this
+        unsafe { libc::close(fd) };
+        Err(e)
+    };
+
+    // SAFETY: sockaddr_nl is plain integers, for which all zeroes is valid
+    let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as u16;
+    addr.nl_groups = CN_IDX_PROC;
+    // SAFETY: addr is a sockaddr_nl and the length passed is its size
+    let bound = unsafe {
+        libc::bind(
+            fd,
+            (&addr as *const libc::sockaddr_nl).cast(),
+            mem::size_of::<libc::sockaddr_nl>() as u32,
+        )
+    };
+    if bound < 0 {
+        return fail(fd);
+    }
+
+    let message = listen_message();
+    // SAFETY: message is valid for reads of message.len() bytes
+    let sent = unsafe { libc::send(fd, message.as_ptr().cast(), message.len(), 0) };
+    if sent < 0 {
+        return fail(fd);
+    }
+    Ok(fd)
+}
+
+/// nlmsghdr + cn_msg + PROC_CN_MCAST_LISTEN
+fn listen_message() -> Vec<u8> {
+    let op = PROC_CN_MCAST_LISTEN.to_ne_bytes();
+    let len = NLMSG_HDRLEN + CN_MSG_LEN + op.len();
+    let mut message = Vec::with_capacity(len);
+    message.extend((len as u32).to_ne_bytes());
+    message.extend((libc::NLMSG_DONE as u16).to_ne_bytes());
+    message.extend(0u16.to_ne_bytes()); // flags
+    message.extend(0u32.to_ne_bytes()); // seq
+    message.extend(0u32.to_ne_bytes()); // pid
+    message.extend(CN_IDX_PROC.to_ne_bytes());
+    message.extend(CN_VAL_PROC.to_ne_bytes());
+    message.extend(0u32.to_ne_bytes()); // seq
+    message.extend(0u32.to_ne_bytes()); // ack
+    message.extend((op.len() as u16).to_ne_bytes());
+    message.extend(0u16.to_ne_bytes()); // flags
+    message.extend(op);
+    message
+}
+
+/// The process events in one datagram. Events about threads other than a
+/// process's main thread are skipped.
+fn parse(mut buf: &[u8]) -> Vec<Event> {
+    let u32_at = |buf: &[u8], at: usize| -> Option<u32> {
+        Some(u32::from_ne_bytes(buf.get(at..at + 4)?.try_into().unwrap()))
+    };
+    let mut events = Vec::new();
+    while let Some(len) = u32_at(buf, 0).map(|len| len as usize) {
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            break;
+        }
+        let event = &buf[NLMSG_HDRLEN + CN_MSG_LEN.min(len - NLMSG_HDRLEN)..len];
+        let data = |field: usize| u32_at(event, EVENT_DATA + field * 4).map(|v| v as i32);
+        let process = |pid: Option<i32>, tgid: Option<i32>| pid.filter(|pid| Some(*pid) == tgid);
+        let parsed = match u32_at(event, 0) {
+            Some(PROC_EVENT_FORK) => process(data(2), data(3)).map(Event::Fork),
+            Some(PROC_EVENT_EXEC) => process(data(0), data(1)).map(Event::Exec),
+            Some(PROC_EVENT_COMM) => process(data(0), data(1)).map(Event::Comm),
+            Some(PROC_EVENT_EXIT) => process(data(0), data(1)).map(Event::Exit),
+            _ => None,
+        };
+        events.extend(parsed);
+        // messages are padded to 4 bytes
+        buf = buf.get(len.next_multiple_of(4)..).unwrap_or_default();
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(what: u32, data: &[i32]) -> Vec<u8> {
+        let mut event = Vec::new();
+        event.extend(what.to_ne_bytes());
+        event.extend(0u32.to_ne_bytes()); // cpu
+        event.extend(0u64.to_ne_bytes()); // timestamp
+        for field in data {
+            event.extend(field.to_ne_bytes());
+        }
+        let mut message = listen_message();
+        message.truncate(NLMSG_HDRLEN + CN_MSG_LEN);
+        let len = message.len() + event.len();
+        message[..4].copy_from_slice(&(len as u32).to_ne_bytes());
+        message.extend(event);
+        message
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut buf = message(PROC_EVENT_FORK, &[1, 1, 500, 500]);
+        // a new thread of 500
+        buf.extend(message(PROC_EVENT_FORK, &[500, 500, 501, 500]));
+        buf.extend(message(PROC_EVENT_EXEC, &[500, 500]));
+        buf.extend(message(PROC_EVENT_EXIT, &[500, 500, 0, 17, 1, 1]));
+        buf.extend(message(0x4, &[500, 500, 0, 0]));
+        assert_eq!(
+            parse(&buf),
+            [Event::Fork(500), Event::Exec(500), Event::Exit(500)]
+        );
+        assert!(parse(&buf[..20]).is_empty());
+    }
+
+    #[test]
+    fn test_short_lived_process_is_seen_once() {
+        let me = std::process::id() as i32;
+        let mut table = Table::default();
+        table.apply(Event::Exec(me));
+        table.apply(Event::Exit(me));
+        assert_eq!(table.snapshot().unwrap().len(), 1);
+        assert!(table.snapshot().unwrap().is_empty());
+
+        // once returned, an exit shows in the next snapshot
+        table.apply(Event::Exec(me));
+        assert_eq!(table.snapshot().unwrap().len(), 1);
+        table.apply(Event::Exit(me));
+        assert!(table.snapshot().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_relevant_events() {
+        let me = Process::read(std::process::id() as i32).unwrap();
+        let mut table = Table {
+            rules: vec![Matcher::Comm("no such game".to_string())],
+            ..Table::default()
+        };
+        assert!(!table.apply(Event::Exec(me.pid)));
+        assert!(!table.apply(Event::Exit(me.pid)));
+
+        table.rules.push(Matcher::Comm(me.comm.clone()));
+        assert!(table.apply(Event::Exec(me.pid)));
+        table.tracked.insert(me.pid);
+        assert!(table.apply(Event::Exit(me.pid)));
+    }
+}
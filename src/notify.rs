@@ -0,0 +1,795 @@
+use crate::budget::{Budget, duration, seconds};
+use crate::log::warn;
+use crate::tracker::OpenSession;
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    io::{self, Read, Write},
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixStream},
+    },
+    sync::mpsc::{self, Sender},
+    thread,
+    time::Duration,
+};
+
+/// The `notifications` config section; leaving it out turns desktop
+/// notifications off.
+///
+/// ```yaml
+/// notifications:
+///     milestones:
+///         - after: 2h
+///         - after: 45m
+///           games: [factorio]
+///     budget_warning: 10m
+/// ```
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+    #[serde(default)]
+    pub milestones: Vec<Milestone>,
+    /// How much time a budget whose games are running has left when it is
+    /// announced; not announced if left out
+    #[serde(default, deserialize_with = "duration")]
+    pub budget_warning: Option<i64>,
+}
+
+/// A reminder once a session has been going for `after`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Milestone {
+    #[serde(deserialize_with = "seconds")]
+    pub after: i64,
+    /// Only for these game ids; all games when empty
+    #[serde(default)]
+    pub games: Vec<String>,
+}
+
+impl Milestone {
+    fn covers(&self, game: &str) -> bool {
+        self.games.is_empty() || self.games.iter().any(|id| id == game)
+    }
+
+    fn passed(&self, session: &OpenSession, now: DateTime<Local>) -> bool {
+        self.covers(&session.game) && (now - session.started).num_seconds() >= self.after
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Notification {
+    summary: String,
+    body: String,
+}
+
+/// Decides when notifications are due and hands them to a thread that
+/// sends them to the session bus, so a slow notification server does not
+/// hold up the daemon.
+#[derive(Default)]
+pub struct Notifier {
+    /// (milestone, game id, session start) already announced
+    shown: HashSet<(usize, String, DateTime<Local>)>,
+    /// budgets announced since they last had more than `budget_warning` left
+    warned: HashSet<usize>,
+    /// to the sending thread, started with the first notification
+    sender: Option<Sender<Notification>>,
+}
+
+impl Notifier {
+    /// Announces milestones the open sessions reached and budgets that got
+    /// low. `remaining` is as returned by `Enforcer::check`.
+    pub fn tick(
+        &mut self,
+        config: &NotifyConfig,
+        sessions: &[OpenSession],
+        budgets: &[Budget],
+        remaining: &[(usize, i64)],
+        now: DateTime<Local>,
+    ) {
+        let mut due = self.milestones(config, sessions, now);
+        due.extend(self.budgets(config, budgets, remaining));
+        for notification in due {
+            self.send(notification);
+        }
+    }
+
+    fn milestones(
+        &mut self,
+        config: &NotifyConfig,
+        sessions: &[OpenSession],
+        now: DateTime<Local>,
+    ) -> Vec<Notification> {
+        self.shown.retain(|(_, game, started)| {
+            sessions
+                .iter()
+                .any(|session| session.game == *game && session.started == *started)
+        });
+        let mut due = Vec::new();
+        for (i, milestone) in config.milestones.iter().enumerate() {
+            for session in sessions {
+                if milestone.passed(session, now)
+                    && self
+                        .shown
+                        .insert((i, session.game.clone(), session.started))
+                {
+                    due.push(Notification {
+                        summary: format!("{} of {}", short_duration(milestone.after), session.name),
+                        body: format!("Playing since {}", session.started.format("%H:%M")),
+                    });
+                }
+            }
+        }
+        due
+    }
+
+    fn budgets(
+        &mut self,
+        config: &NotifyConfig,
+        budgets: &[Budget],
+        remaining: &[(usize, i64)],
+    ) -> Vec<Notification> {
+        let Some(before) = config.budget_warning else {
+            return Vec::new();
+        };
+        let mut due = Vec::new();
+        for &(i, left) in remaining {
+            if left > before {
+                self.warned.remove(&i);
+            } else if self.warned.insert(i) {
+                due.push(Notification {
+                    summary: match left {
+                        0 => "Play time is up".to_string(),
+                        _ => format!("{} of play time left", short_duration(left)),
+                    },
+                    body: format!("Budget for {}", budgets[i].describe()),
+                });
+            }
+        }
+        due
+    }
+
+    fn send(&mut self, notification: Notification) {
+        let sender = self.sender.get_or_insert_with(|| {
+            let (sender, notifications) = mpsc::channel::<Notification>();
+            thread::spawn(move || {
+                // connected on demand, so the daemon may start before the
+                // desktop session does
+                let mut bus = None;
+                for notification in notifications {
+                    let result = match &mut bus {
+                        Some(bus) => notify(bus, &notification),
+                        None => Bus::session().and_then(|mut new| {
+                            let result = notify(&mut new, &notification);
+                            bus = Some(new);
+                            result
+                        }),
+                    };
+                    if let Err(e) = result {
+                        warn!("Cannot show notification {:?}: {}", notification.summary, e);
+                        bus = None;
+                    }
+                }
+            });
+            sender
+        });
+        if sender.send(notification).is_err() {
+            self.sender = None;
+        }
+    }
+
+    /// Forgets which budgets were announced, for when the budgets changed.
+    pub fn reset_budgets(&mut self) {
+        self.warned.clear();
+    }
+
+    /// Takes the milestones of a new config as shown where the open
+    /// sessions are already past them, so a reload announces none again.
+    pub fn reset_milestones(
+        &mut self,
+        config: &NotifyConfig,
+        sessions: &[OpenSession],
+        now: DateTime<Local>,
+    ) {
+        self.shown.clear();
+        for (i, milestone) in config.milestones.iter().enumerate() {
+            for session in sessions {
+                if milestone.passed(session, now) {
+                    self.shown
+                        .insert((i, session.game.clone(), session.started));
+                }
+            }
+        }
+    }
+}
+
+/// `2h`, `45m` or `1h 30m`; seconds only when there are no minutes.
+fn short_duration(secs: i64) -> String {
+    let (h, m) = (secs / 3600, secs % 3600 / 60);
+    match (h, m) {
+        (0, 0) => format!("{}s", secs),
+        (0, m) => format!("{}m", m),
+        (h, 0) => format!("{}h", h),
+        (h, m) => format!("{}h {}m", h, m),
+    }
+}
+
+/// Shows `notification` through org.freedesktop.Notifications and returns
+/// the id the server gave it.
+fn notify(bus: &mut Bus, notification: &Notification) -> Result<u32, String> {
+    let mut body = Writer::default();
+    body.string("playtime"); // app_name
+    body.u32(0); // replaces_id
+    body.string(""); // app_icon
+    body.string(&notification.summary);
+    body.string(&notification.body);
+    body.u32(0); // no actions
+    body.u32(0); // no hints
+    body.align(8);
+    body.i32(-1); // expire_timeout: the server's default
+    let reply = bus.call(Message {
+        destination: Some("org.freedesktop.Notifications".to_string()),
+        path: Some("/org/freedesktop/Notifications".to_string()),
+        interface: Some("org.freedesktop.Notifications".to_string()),
+        member: Some("Notify".to_string()),
+        signature: "susssasa{sv}i".to_string(),
+        body: body.buf,
+        ..Message::default()
+    })?;
+    reply.reader().u32()
+}
+
+// message types and header fields, from the D-Bus specification
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const PATH: u8 = 1;
+const INTERFACE: u8 = 2;
+const MEMBER: u8 = 3;
+const ERROR_NAME: u8 = 4;
+const REPLY_SERIAL: u8 = 5;
+const DESTINATION: u8 = 6;
+const SENDER: u8 = 7;
+const SIGNATURE: u8 = 8;
+/// Longest message the specification allows, 128 MiB
+const MAX_MESSAGE: usize = 1 << 27;
+
+/// A connection to a D-Bus message bus, speaking just enough of the
+/// protocol to call methods and answer them.
+struct Bus {
+    stream: UnixStream,
+    serial: u32,
+}
+
+impl Bus {
+    /// The user's session bus, from `DBUS_SESSION_BUS_ADDRESS` or else
+    /// `$XDG_RUNTIME_DIR/bus`.
+    fn session() -> Result<Bus, String> {
+        let address = env::var("DBUS_SESSION_BUS_ADDRESS")
+            .ok()
+            .or_else(|| {
+                Some(format!(
+                    "unix:path={}/bus",
+                    env::var("XDG_RUNTIME_DIR").ok()?
+                ))
+            })
+            .ok_or("no session bus address")?;
+        Bus::connect(&address)
+    }
+
+    /// Connects to the first reachable `unix:` address in `address`,
+    /// authenticates and says hello.
+    fn connect(address: &str) -> Result<Bus, String> {
+        let stream = open(address).map_err(|e| format!("cannot connect to {}: {}", address, e))?;
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .map_err(|e| e.to_string())?;
+        let mut bus = Bus { stream, serial: 0 };
+        bus.authenticate()
+            .map_err(|e| format!("cannot authenticate to {}: {}", address, e))?;
+        bus.call(Message {
+            destination: Some("org.freedesktop.DBus".to_string()),
+            path: Some("/org/freedesktop/DBus".to_string()),
+            interface: Some("org.freedesktop.DBus".to_string()),
+            member: Some("Hello".to_string()),
+            ..Message::default()
+        })?;
+        Ok(bus)
+    }
+
+    /// The EXTERNAL mechanism: the bus checks our uid on the socket.
+    fn authenticate(&mut self) -> io::Result<()> {
+        // SAFETY: getuid has no preconditions and cannot fail
+        let uid = unsafe { libc::getuid() }.to_string();
+        let hex: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+        self.stream
+            .write_all(format!("\0AUTH EXTERNAL {}\r\n", hex).as_bytes())?;
+        let mut line = Vec::new();
+        while !line.ends_with(b"\r\n") {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte)?;
+            line.push(byte[0]);
+        }
+        if !line.starts_with(b"OK ") {
+            let reply = String::from_utf8_lossy(&line).trim_end().to_string();
+            return Err(io::Error::other(format!("refused: {}", reply)));
+        }
+        self.stream.write_all(b"BEGIN\r\n")
+    }
+
+    /// Sends `message` with the next serial, which is returned.
+    fn send(&mut self, mut message: Message) -> Result<u32, String> {
+        self.serial += 1;
+        message.serial = self.serial;
+        self.stream
+            .write_all(&message.encode())
+            .map_err(|e| e.to_string())?;
+        Ok(self.serial)
+    }
+
+    fn receive(&mut self) -> Result<Message, String> {
+        let mut buf = vec![0; 16];
+        self.stream
+            .read_exact(&mut buf)
+            .map_err(|e| e.to_string())?;
+        let mut fixed = Reader::new(&buf, buf[0] == b'B');
+        fixed.take(4)?;
+        let body = fixed.u32()? as usize;
+        fixed.u32()?;
+        let fields = fixed.u32()? as usize;
+        let len = (16 + fields).next_multiple_of(8) + body;
+        if len > MAX_MESSAGE {
+            return Err(format!("D-Bus message of {} bytes is too long", len));
+        }
+        buf.resize(len, 0);
+        self.stream
+            .read_exact(&mut buf[16..])
+            .map_err(|e| e.to_string())?;
+        Message::decode(&buf)
+    }
+
+    /// Sends a method call and waits for its reply, skipping signals and
+    /// other messages that arrive in the meantime.
+    fn call(&mut self, mut message: Message) -> Result<Message, String> {
+        message.kind = METHOD_CALL;
+        let serial = self.send(message)?;
+        loop {
+            let reply = self.receive()?;
+            if reply.reply_serial != Some(serial) {
+                continue;
+            }
+            match reply.kind {
+                METHOD_RETURN => return Ok(reply),
+                ERROR => {
+                    let name = reply.error_name.clone().unwrap_or_default();
+                    return Err(match reply.signature.starts_with('s') {
+                        true => format!("{}: {}", name, reply.reader().string()?),
+                        false => name,
+                    });
+                }
+                kind => return Err(format!("unexpected reply of type {}", kind)),
+            }
+        }
+    }
+}
+
+fn open(address: &str) -> io::Result<UnixStream> {
+    let mut error = io::Error::new(io::ErrorKind::InvalidInput, "no unix: address");
+    for part in address.split(';') {
+        let Some(params) = part.strip_prefix("unix:") else {
+            continue;
+        };
+        let params: HashMap<&str, &str> = params
+            .split(',')
+            .filter_map(|param| param.split_once('='))
+            .collect();
+        let addr = match (params.get("path"), params.get("abstract")) {
+            (Some(path), _) => SocketAddr::from_pathname(path)?,
+            (None, Some(name)) => SocketAddr::from_abstract_name(name)?,
+            (None, None) => continue,
+        };
+        match UnixStream::connect_addr(&addr) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+#[derive(Default, Debug)]
+struct Message {
+    kind: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    destination: Option<String>,
+    sender: Option<String>,
+    signature: String,
+    body: Vec<u8>,
+    /// Byte order of `body`, from the header of a received message; what
+    /// we send is always little-endian.
+    big_endian: bool,
+}
+
+impl Message {
+    /// In little-endian byte order, whatever `big_endian` says.
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Writer::default();
+        out.buf.extend([b'l', self.kind, 0, 1]);
+        out.u32(self.body.len() as u32);
+        out.u32(self.serial);
+        out.u32(0); // length of the header fields, filled in below
+        let strings = [
+            (PATH, "o", &self.path),
+            (INTERFACE, "s", &self.interface),
+            (MEMBER, "s", &self.member),
+            (ERROR_NAME, "s", &self.error_name),
+            (DESTINATION, "s", &self.destination),
+            (SENDER, "s", &self.sender),
+        ];
+        for (code, kind, value) in strings {
+            if let Some(value) = value {
+                out.align(8);
+                out.buf.push(code);
+                out.signature(kind);
+                out.string(value);
+            }
+        }
+        if let Some(serial) = self.reply_serial {
+            out.align(8);
+            out.buf.push(REPLY_SERIAL);
+            out.signature("u");
+            out.u32(serial);
+        }
+        if !self.signature.is_empty() {
+            out.align(8);
+            out.buf.push(SIGNATURE);
+            out.signature("g");
+            out.signature(&self.signature);
+        }
+        let fields = (out.buf.len() - 16) as u32;
+        out.buf[12..16].copy_from_slice(&fields.to_le_bytes());
+        out.align(8);
+        out.buf.extend(&self.body);
+        out.buf
+    }
+
+    fn decode(buf: &[u8]) -> Result<Message, String> {
+        let big_endian = buf.first() == Some(&b'B');
+        let mut reader = Reader::new(buf, big_endian);
+        let mut message = Message {
+            kind: reader.take(4)?[1],
+            big_endian,
+            ..Message::default()
+        };
+        let body = reader.u32()? as usize;
+        message.serial = reader.u32()?;
+        let end = reader.u32()? as usize + reader.pos;
+        while reader.pos < end {
+            reader.align(8);
+            let code = reader.take(1)?[0];
+            let kind = reader.signature()?;
+            match (code, kind.as_str()) {
+                (REPLY_SERIAL, "u") => message.reply_serial = Some(reader.u32()?),
+                (SIGNATURE, "g") => message.signature = reader.signature()?,
+                (_, "s" | "o") => {
+                    let value = Some(reader.string()?);
+                    match code {
+                        PATH => message.path = value,
+                        INTERFACE => message.interface = value,
+                        MEMBER => message.member = value,
+                        ERROR_NAME => message.error_name = value,
+                        DESTINATION => message.destination = value,
+                        SENDER => message.sender = value,
+                        _ => {}
+                    }
+                }
+                (_, "u") => {
+                    reader.u32()?;
+                }
+                (_, "g") => {
+                    reader.signature()?;
+                }
+                _ => return Err(format!("unexpected header field of type {:?}", kind)),
+            }
+        }
+        reader.align(8);
+        message.body = reader.take(body)?.to_vec();
+        Ok(message)
+    }
+
+    fn reader(&self) -> Reader<'_> {
+        Reader::new(&self.body, self.big_endian)
+    }
+}
+
+/// Marshals values in the D-Bus wire format, little-endian as `encode`
+/// declares in the header.
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, n: usize) {
+        self.buf.resize(self.buf.len().next_multiple_of(n), 0);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.buf.extend(value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.align(4);
+        self.buf.extend(value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.buf.extend(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, value: &str) {
+        self.buf.push(value.len() as u8);
+        self.buf.extend(value.as_bytes());
+        self.buf.push(0);
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8], big_endian: bool) -> Reader<'a> {
+        Reader {
+            buf,
+            pos: 0,
+            big_endian,
+        }
+    }
+
+    fn align(&mut self, n: usize) {
+        self.pos = self.pos.next_multiple_of(n);
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + n)
+            .ok_or("truncated D-Bus message")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        self.align(4);
+        let bytes = self.take(4)?.try_into().unwrap();
+        Ok(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        let value = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(value)
+    }
+
+    fn signature(&mut self) -> Result<String, String> {
+        let len = self.take(1)?[0] as usize;
+        let value = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+    use std::{
+        fs,
+        io::{BufRead, BufReader},
+        process::{Command, Stdio},
+    };
+
+    #[test]
+    fn test_milestones_and_warnings() {
+        let config: NotifyConfig = serde_yaml::from_str(
+            "{milestones: [{after: 2h}, {after: 30m, games: [tetris]}], budget_warning: 10m}",
+        )
+        .unwrap();
+        let start = Local::now();
+        let sessions = [OpenSession {
+            game: "factorio".to_string(),
+            name: "Factorio".to_string(),
+            pid: 1,
+            started: start,
+            processes: 1,
+            paused: false,
+        }];
+        let at = |mins: i64| start + TimeDelta::minutes(mins);
+        let mut notifier = Notifier::default();
+        assert!(notifier.milestones(&config, &sessions, at(119)).is_empty());
+        let due = notifier.milestones(&config, &sessions, at(120));
+        assert_eq!(due[0].summary, "2h of Factorio");
+        assert!(notifier.milestones(&config, &sessions, at(125)).is_empty());
+        // a new session gets its own
+        notifier.milestones(&config, &[], at(126));
+        assert!(notifier.shown.is_empty());
+
+        // a reload does not repeat milestones already passed
+        notifier.reset_milestones(&config, &sessions, at(130));
+        assert!(notifier.milestones(&config, &sessions, at(131)).is_empty());
+
+        let budgets: Vec<Budget> = vec![serde_yaml::from_str("daily: 1h").unwrap()];
+        assert!(notifier.budgets(&config, &budgets, &[(0, 700)]).is_empty());
+        let due = notifier.budgets(&config, &budgets, &[(0, 540)]);
+        assert_eq!(
+            due,
+            [Notification {
+                summary: "9m of play time left".to_string(),
+                body: "Budget for all games".to_string(),
+            }]
+        );
+        assert!(notifier.budgets(&config, &budgets, &[(0, 0)]).is_empty());
+    }
+
+    #[test]
+    fn test_message() {
+        let message = Message {
+            kind: METHOD_RETURN,
+            serial: 7,
+            reply_serial: Some(3),
+            destination: Some(":1.5".to_string()),
+            signature: "u".to_string(),
+            body: 42u32.to_le_bytes().to_vec(),
+            ..Message::default()
+        };
+        let decoded = Message::decode(&message.encode()).unwrap();
+        assert_eq!((decoded.kind, decoded.serial), (METHOD_RETURN, 7));
+        assert_eq!(decoded.reply_serial, Some(3));
+        assert_eq!(decoded.destination.as_deref(), Some(":1.5"));
+        assert_eq!(decoded.reader().u32(), Ok(42));
+        assert!(Message::decode(&message.encode()[..20]).is_err());
+
+        // a method return of 42 as a big-endian peer sends it
+        let mut big = vec![b'B', METHOD_RETURN, 0, 1];
+        big.extend(4u32.to_be_bytes());
+        big.extend(9u32.to_be_bytes());
+        big.extend(7u32.to_be_bytes());
+        big.extend([SIGNATURE, 1, b'g', 0, 1, b'u', 0, 0]);
+        big.extend(42u32.to_be_bytes());
+        let decoded = Message::decode(&big).unwrap();
+        assert_eq!(decoded.serial, 9);
+        assert_eq!(decoded.reader().u32(), Ok(42));
+    }
+
+    #[test]
+    fn test_message_too_long() {
+        let (stream, mut peer) = UnixStream::pair().unwrap();
+        let mut header = vec![b'l', METHOD_RETURN, 0, 1];
+        header.extend(u32::MAX.to_le_bytes()); // body
+        header.extend(1u32.to_le_bytes()); // serial
+        header.extend(0u32.to_le_bytes()); // header fields
+        peer.write_all(&header).unwrap();
+        let mut bus = Bus { stream, serial: 0 };
+        assert!(bus.receive().unwrap_err().contains("too long"));
+    }
+
+    /// Against a private dbus-daemon, with a fake notification server on
+    /// it; skipped where dbus-daemon is not installed.
+    #[test]
+    fn test_notify_over_dbus() {
+        let dir = std::env::temp_dir().join(format!("playtime-dbus-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("bus.conf");
+        fs::write(
+            &config,
+            format!(
+                "<busconfig>
+                    <listen>unix:dir={}</listen>
+                    <auth>EXTERNAL</auth>
+                    <policy context=\"default\">
+                        <allow send_destination=\"*\"/>
+                        <allow receive_sender=\"*\"/>
+                        <allow own=\"*\"/>
+                    </policy>
+                </busconfig>",
+                dir.display()
+            ),
+        )
+        .unwrap();
+        let Ok(mut daemon) = Command::new("dbus-daemon")
+            .arg(format!("--config-file={}", config.display()))
+            .args(["--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        let address = address.trim().to_string();
+
+        let (ready, started) = mpsc::channel();
+        let server = {
+            let address = address.clone();
+            thread::spawn(move || {
+                let mut bus = Bus::connect(&address).unwrap();
+                let mut name = Writer::default();
+                name.string("org.freedesktop.Notifications");
+                name.u32(4); // DBUS_NAME_FLAG_DO_NOT_QUEUE
+                bus.call(Message {
+                    destination: Some("org.freedesktop.DBus".to_string()),
+                    path: Some("/org/freedesktop/DBus".to_string()),
+                    interface: Some("org.freedesktop.DBus".to_string()),
+                    member: Some("RequestName".to_string()),
+                    signature: "su".to_string(),
+                    body: name.buf,
+                    ..Message::default()
+                })
+                .unwrap();
+                ready.send(()).unwrap();
+                loop {
+                    let call = bus.receive().unwrap();
+                    if call.kind != METHOD_CALL || call.member.as_deref() != Some("Notify") {
+                        continue;
+                    }
+                    let mut body = call.reader();
+                    let fields: Vec<String> = (0..5)
+                        .map(|i| match i {
+                            1 => body.u32().map(|id| id.to_string()),
+                            _ => body.string(),
+                        })
+                        .collect::<Result<_, _>>()
+                        .unwrap();
+                    bus.send(Message {
+                        kind: METHOD_RETURN,
+                        reply_serial: Some(call.serial),
+                        destination: call.sender,
+                        signature: "u".to_string(),
+                        body: 17u32.to_le_bytes().to_vec(),
+                        ..Message::default()
+                    })
+                    .unwrap();
+                    return fields;
+                }
+            })
+        };
+        started.recv().unwrap();
+
+        let mut bus = Bus::connect(&address).unwrap();
+        let notification = Notification {
+            summary: "2h of Factorio".to_string(),
+            body: "Playing since 14:05".to_string(),
+        };
+        assert_eq!(notify(&mut bus, &notification), Ok(17));
+        assert_eq!(
+            server.join().unwrap(),
+            ["playtime", "0", "", "2h of Factorio", "Playing since 14:05"]
+        );
+        // nobody serves notifications any more
+        assert!(notify(&mut bus, &notification).is_err());
+
+        let _ = daemon.kill();
+        let _ = daemon.wait();
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,78 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// A flat record for machine-readable output. `FIELDS` fixes the CSV
+/// column order and must list every serialized field.
+pub trait Record: Serialize {
+    const FIELDS: &'static [&'static str];
+}
+
+pub fn print_json<T: Record>(records: &[T]) {
+    println!("{}", serde_json::to_string_pretty(records).unwrap());
+}
+
+pub fn print_csv<T: Record>(records: &[T]) {
+    print!("{}", to_csv(records));
+}
+
+fn to_csv<T: Record>(records: &[T]) -> String {
+    let mut out = T::FIELDS.join(",");
+    out.push('\n');
+    for record in records {
+        let value = serde_json::to_value(record).unwrap();
+        let fields: Vec<String> = T::FIELDS
+            .iter()
+            .map(|field| match &value[*field] {
+                Value::Null => String::new(),
+                Value::String(s) => csv_field(s),
+                other => other.to_string(),
+            })
+            .collect();
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// Quotes a field if it contains a separator, quote or line break.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Row {
+        name: &'static str,
+        secs: Option<i64>,
+    }
+
+    impl Record for Row {
+        const FIELDS: &'static [&'static str] = &["name", "secs"];
+    }
+
+    #[test]
+    fn test_csv() {
+        let rows = [
+            Row {
+                name: "Hollow Knight",
+                secs: Some(60),
+            },
+            Row {
+                name: "Say \"hi\", world",
+                secs: None,
+            },
+        ];
+        assert_eq!(
+            to_csv(&rows),
+            "name,secs\nHollow Knight,60\n\"Say \"\"hi\"\", world\",\n"
+        );
+        assert_eq!(to_csv::<Row>(&[]), "name,secs\n");
+    }
+}
@@ -0,0 +1,263 @@
+use chrono::{
+    DateTime, Datelike, Days, Local, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeZone,
+};
+use clap::ValueEnum;
+
+/// A half-open span of local time, `start..end`.
+pub type Period = (DateTime<Local>, DateTime<Local>);
+
+/// Parses a named or calendar period relative to today:
+///
+/// - `today`, `yesterday`
+/// - `this-week`, `last-week` (weeks start on Monday)
+/// - `this-month`, `last-month`, `this-year`, `last-year`
+/// - `2025-06-01`, `2025-06`, `2025`
+/// - `2025-06-01..2025-06-07`, both days included
+pub fn parse_period(s: &str) -> Result<Period, String> {
+    parse_period_at(s, Local::now().date_naive())
+}
+
+fn parse_period_at(s: &str, today: NaiveDate) -> Result<Period, String> {
+    let (first, last) = period_days(s, today).ok_or_else(|| format!("invalid period {:?}", s))?;
+    if last < first {
+        return Err(format!("period {:?} ends before it starts", s));
+    }
+    Ok((start_of_day(first), start_of_day(last + Days::new(1))))
+}
+
+/// First and last day (inclusive) of a period.
+fn period_days(s: &str, today: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+    let name = s.trim().to_lowercase().replace([' ', '_'], "-");
+    let week = today - Days::new(today.weekday().num_days_from_monday() as u64);
+    let month = today.with_day(1)?;
+    let year = month.with_month(1)?;
+    let days = match name.as_str() {
+        "today" => (today, today),
+        "yesterday" => (today.pred_opt()?, today.pred_opt()?),
+        "this-week" => (week, week + Days::new(6)),
+        "last-week" => (week - Days::new(7), week - Days::new(1)),
+        "this-month" => (month, month + Months::new(1) - Days::new(1)),
+        "last-month" => (month - Months::new(1), month - Days::new(1)),
+        "this-year" => (year, year + Months::new(12) - Days::new(1)),
+        "last-year" => (year - Months::new(12), year - Days::new(1)),
+        _ => {
+            if let Some((from, to)) = name.split_once("..") {
+                return Some((period_days(from, today)?.0, period_days(to, today)?.1));
+            }
+            if let Ok(day) = NaiveDate::parse_from_str(&name, "%Y-%m-%d") {
+                (day, day)
+            } else if let Ok(month) = NaiveDate::parse_from_str(&format!("{}-01", name), "%Y-%m-%d")
+            {
+                (month, month + Months::new(1) - Days::new(1))
+            } else {
+                let year = NaiveDate::from_ymd_opt(name.parse().ok()?, 1, 1)?;
+                (year, year + Months::new(12) - Days::new(1))
+            }
+        }
+    };
+    Some(days)
+}
+
+/// Calendar buckets for breakdowns.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Interval {
+    Day,
+    /// ISO weeks, Monday to Sunday
+    Week,
+    Month,
+}
+
+impl Interval {
+    /// First day of the bucket containing `day`.
+    pub fn bucket(self, day: NaiveDate) -> NaiveDate {
+        match self {
+            Interval::Day => day,
+            Interval::Week => day - Days::new(day.weekday().num_days_from_monday() as u64),
+            Interval::Month => day - Days::new(day.day0() as u64),
+        }
+    }
+
+    /// First day of the bucket after the one starting on `bucket`.
+    pub fn next(self, bucket: NaiveDate) -> NaiveDate {
+        match self {
+            Interval::Day => bucket + Days::new(1),
+            Interval::Week => bucket + Days::new(7),
+            Interval::Month => bucket + Months::new(1),
+        }
+    }
+
+    pub fn label(self, bucket: NaiveDate) -> String {
+        match self {
+            Interval::Day => bucket.format("%Y-%m-%d %a").to_string(),
+            Interval::Week => bucket.format("%G-W%V").to_string(),
+            Interval::Month => bucket.format("%Y-%m").to_string(),
+        }
+    }
+
+    /// Cuts `start..end` at bucket boundaries (local midnight), returning
+    /// the seconds falling into each bucket.
+    pub fn split(self, start: DateTime<Local>, end: DateTime<Local>) -> Vec<(NaiveDate, i64)> {
+        let mut pieces = Vec::new();
+        let mut from = start;
+        while from < end {
+            let bucket = self.bucket(from.date_naive());
+            let to = start_of_day(self.next(bucket)).min(end);
+            pieces.push((bucket, (to - from).num_seconds()));
+            from = to;
+        }
+        pieces
+    }
+}
+
+/// Local midnight at the start of `day`, or the first instant after it on
+/// the rare days where a DST change skips midnight.
+pub fn start_of_day(day: NaiveDate) -> DateTime<Local> {
+    let midnight = day.and_time(NaiveTime::MIN);
+    (0..=3)
+        .find_map(|hour| {
+            Local
+                .from_local_datetime(&(midnight + chrono::TimeDelta::hours(hour)))
+                .earliest()
+        })
+        .unwrap_or_else(|| Local.from_utc_datetime(&midnight))
+}
+
+/// Parses a point in time. Dates without a time mean local midnight at the
+/// start of that day.
+pub fn parse_time(s: &str) -> Result<DateTime<Local>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Local));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(start_of_day(date));
+    }
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M"))
+        .map_err(|_| format!("invalid time {:?}", s))?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(|| format!("{:?} does not exist in the local time zone", s))
+}
+
+/// Sorts `periods` and joins the ones that overlap or touch.
+pub fn merge(mut periods: Vec<Period>) -> Vec<Period> {
+    periods.sort();
+    let mut merged: Vec<Period> = Vec::new();
+    for (from, to) in periods {
+        match merged.last_mut() {
+            Some(last) if from <= last.1 => last.1 = last.1.max(to),
+            _ => merged.push((from, to)),
+        }
+    }
+    merged
+}
+
+/// What is left of `periods` outside `holes`; both must be sorted and
+/// non-overlapping.
+pub fn subtract(periods: &[Period], holes: &[Period]) -> Vec<Period> {
+    let mut left = Vec::new();
+    for &(start, end) in periods {
+        let mut at = start;
+        for &(from, to) in holes {
+            if to <= at || from >= end {
+                continue;
+            }
+            if at < from {
+                left.push((at, from));
+            }
+            at = at.max(to);
+        }
+        if at < end {
+            left.push((at, end));
+        }
+    }
+    left
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn days(s: &str) -> (String, String) {
+        // Wednesday
+        let today = NaiveDate::from_ymd_opt(2025, 3, 12).unwrap();
+        let (start, end) = parse_period_at(s, today).unwrap();
+        (
+            start.format("%Y-%m-%d %H:%M").to_string(),
+            end.format("%Y-%m-%d %H:%M").to_string(),
+        )
+    }
+
+    fn pair(start: &str, end: &str) -> (String, String) {
+        (format!("{} 00:00", start), format!("{} 00:00", end))
+    }
+
+    #[test]
+    fn test_named_periods() {
+        assert_eq!(days("today"), pair("2025-03-12", "2025-03-13"));
+        assert_eq!(days("yesterday"), pair("2025-03-11", "2025-03-12"));
+        assert_eq!(days("this week"), pair("2025-03-10", "2025-03-17"));
+        assert_eq!(days("last-week"), pair("2025-03-03", "2025-03-10"));
+        assert_eq!(days("this_month"), pair("2025-03-01", "2025-04-01"));
+        assert_eq!(days("last-month"), pair("2025-02-01", "2025-03-01"));
+        assert_eq!(days("last-year"), pair("2024-01-01", "2025-01-01"));
+    }
+
+    #[test]
+    fn test_calendar_periods() {
+        assert_eq!(days("2024-02-28"), pair("2024-02-28", "2024-02-29"));
+        assert_eq!(days("2024-02"), pair("2024-02-01", "2024-03-01"));
+        assert_eq!(days("2024"), pair("2024-01-01", "2025-01-01"));
+        assert_eq!(
+            days("2024-12-30..2025-01-02"),
+            pair("2024-12-30", "2025-01-03")
+        );
+        assert_eq!(days("last-month..today"), pair("2025-02-01", "2025-03-13"));
+
+        let today = NaiveDate::from_ymd_opt(2025, 3, 12).unwrap();
+        assert!(parse_period_at("fortnight", today).is_err());
+        assert!(parse_period_at("2025-13", today).is_err());
+        assert!(parse_period_at("2025-03-02..2025-03-01", today).is_err());
+    }
+
+    #[test]
+    fn test_split_at_midnight() {
+        let start = parse_time("2025-03-09 23:00").unwrap();
+        let end = parse_time("2025-03-10 01:30").unwrap();
+        let labels = |interval: Interval| -> Vec<(String, i64)> {
+            interval
+                .split(start, end)
+                .into_iter()
+                .map(|(bucket, secs)| (interval.label(bucket), secs))
+                .collect()
+        };
+        assert_eq!(
+            labels(Interval::Day),
+            [
+                ("2025-03-09 Sun".to_string(), 3600),
+                ("2025-03-10 Mon".to_string(), 5400)
+            ]
+        );
+        // Sunday and Monday are in different ISO weeks
+        assert_eq!(
+            labels(Interval::Week),
+            [
+                ("2025-W10".to_string(), 3600),
+                ("2025-W11".to_string(), 5400)
+            ]
+        );
+        assert_eq!(labels(Interval::Month), [("2025-03".to_string(), 9000)]);
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let midnight = parse_time("2025-06-01").unwrap();
+        assert_eq!(midnight.to_rfc3339().get(..19), Some("2025-06-01T00:00:00"));
+        let evening = parse_time("2025-06-01 18:30").unwrap();
+        assert_eq!((evening - midnight).num_minutes(), 18 * 60 + 30);
+        let exact = parse_time("2025-06-01T18:30:00+00:00").unwrap();
+        assert_eq!(exact.timestamp(), 1748802600);
+        assert!(parse_time("yesterday-ish").is_err());
+    }
+}
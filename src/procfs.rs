@@ -0,0 +1,214 @@
+use chrono::{DateTime, Local, TimeZone};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+/// A process, as opposed to a pid: pids get reused, but no two processes
+/// with the same pid start at the same clock tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProcessId {
+    pub pid: i32,
+    pub starttime: u64,
+}
+
+/// A process found under /proc. `exe` and `cmdline` are only read when a
+/// matcher asks for them, since most scans only need what is in `stat`;
+/// clones share what was read.
+#[derive(Clone)]
+pub struct Process {
+    pub pid: i32,
+    pub ppid: i32,
+    pub comm: String,
+    /// Clock ticks after boot at which the process started.
+    pub starttime: u64,
+    exe: Arc<OnceLock<Option<PathBuf>>>,
+    cmdline: Arc<OnceLock<Option<String>>>,
+}
+
+impl Process {
+    pub fn read(pid: i32) -> Option<Process> {
+        let stat = fs::read_to_string(proc_path(pid, "stat")).ok()?;
+        let stat = Stat::parse(&stat)?;
+        Some(Process {
+            pid,
+            ppid: stat.ppid,
+            comm: stat.comm,
+            starttime: stat.starttime,
+            exe: Arc::default(),
+            cmdline: Arc::default(),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn fake(
+        pid: i32,
+        ppid: i32,
+        comm: &str,
+        exe: Option<&str>,
+        cmdline: Option<&str>,
+    ) -> Process {
+        Process {
+            pid,
+            ppid,
+            comm: comm.to_string(),
+            starttime: 0,
+            exe: Arc::new(OnceLock::from(exe.map(PathBuf::from))),
+            cmdline: Arc::new(OnceLock::from(cmdline.map(String::from))),
+        }
+    }
+
+    /// Wall-clock start time, from boot time plus `starttime`.
+    pub fn started(&self) -> io::Result<DateTime<Local>> {
+        let millis = boot_time()? * 1000 + (self.starttime * 1000 / clock_ticks()) as i64;
+        Local
+            .timestamp_millis_opt(millis)
+            .single()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "start time out of range"))
+    }
+
+    pub fn id(&self) -> ProcessId {
+        ProcessId {
+            pid: self.pid,
+            starttime: self.starttime,
+        }
+    }
+
+    /// Resolved target of /proc/<pid>/exe. Unreadable for other users'
+    /// processes unless we run as root.
+    pub fn exe(&self) -> Option<&Path> {
+        self.exe
+            .get_or_init(|| fs::read_link(proc_path(self.pid, "exe")).ok())
+            .as_deref()
+    }
+
+    /// /proc/<pid>/cmdline with the NUL separators replaced by spaces.
+    pub fn cmdline(&self) -> Option<&str> {
+        self.cmdline
+            .get_or_init(|| {
+                let raw = fs::read(proc_path(self.pid, "cmdline")).ok()?;
+                let args: Vec<_> = raw
+                    .split(|b| *b == 0)
+                    .filter(|arg| !arg.is_empty())
+                    .map(String::from_utf8_lossy)
+                    .collect();
+                Some(args.join(" "))
+            })
+            .as_deref()
+    }
+}
+
+/// The fields we use from /proc/<pid>/stat.
+struct Stat {
+    comm: String,
+    ppid: i32,
+    starttime: u64,
+}
+
+impl Stat {
+    /// comm is wrapped in parentheses and may itself contain spaces and
+    /// parentheses, so the remaining fields start after the last `)`.
+    fn parse(stat: &str) -> Option<Stat> {
+        let open = stat.find('(')?;
+        let close = stat.rfind(')')?;
+        let comm = stat.get(open + 1..close)?.to_string();
+        // fields after comm start at field 3 (state)
+        let fields: Vec<&str> = stat.get(close + 1..)?.split_whitespace().collect();
+        let field = |n: usize| fields.get(n - 3);
+        Some(Stat {
+            comm,
+            ppid: field(4)?.parse().ok()?,
+            starttime: field(22)?.parse().ok()?,
+        })
+    }
+}
+
+/// Boot time in seconds since the epoch, from the `btime` line of /proc/stat.
+fn boot_time() -> io::Result<i64> {
+    static BTIME: OnceLock<i64> = OnceLock::new();
+    if let Some(btime) = BTIME.get() {
+        return Ok(*btime);
+    }
+    let stat = fs::read_to_string("/proc/stat")?;
+    let btime = parse_btime(&stat)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no btime in /proc/stat"))?;
+    Ok(*BTIME.get_or_init(|| btime))
+}
+
+fn parse_btime(stat: &str) -> Option<i64> {
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|btime| btime.trim().parse().ok())
+}
+
+/// Units of `starttime` per second (USER_HZ).
+fn clock_ticks() -> u64 {
+    // SAFETY: sysconf has no preconditions
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
+
+/// All numeric entries in /proc.
+pub fn pids() -> io::Result<Vec<i32>> {
+    let mut pids = Vec::new();
+    for entry in fs::read_dir("/proc")?.flatten() {
+        if let Ok(pid) = entry.file_name().to_string_lossy().parse::<i32>() {
+            pids.push(pid);
+        }
+    }
+    Ok(pids)
+}
+
+fn proc_path(pid: i32, file: &str) -> PathBuf {
+    let mut path = PathBuf::from("/proc");
+    path.push(pid.to_string());
+    path.push(file);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = Stat::parse(
+            "4242 (Web Content) S 4100 4242 4100 0 -1 4194560 1 0 0 0 \
+            12 3 0 0 20 0 28 0 1534902 3112345600 74032",
+        )
+        .unwrap();
+        assert_eq!(stat.comm, "Web Content");
+        assert_eq!(stat.ppid, 4100);
+        assert_eq!(stat.starttime, 1534902);
+
+        let stat =
+            Stat::parse("17 (a) b) (c) R 1 17 17 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 99 0 0").unwrap();
+        assert_eq!(stat.comm, "a) b) (c");
+        assert_eq!(stat.ppid, 1);
+        assert_eq!(stat.starttime, 99);
+
+        assert!(Stat::parse("17 (truncated").is_none());
+        assert!(Stat::parse("17 (short) S 1 17").is_none());
+    }
+
+    #[test]
+    fn test_parse_btime() {
+        let stat = "cpu  1 2 3\nintr 100\nctxt 5\nbtime 1760400000\nprocesses 9\n";
+        assert_eq!(parse_btime(stat), Some(1760400000));
+        assert_eq!(parse_btime("cpu 1 2 3\n"), None);
+    }
+
+    #[test]
+    fn test_read_self() {
+        let me = Process::read(std::process::id() as i32).unwrap();
+        assert_eq!(me.ppid as u32, std::os::unix::process::parent_id());
+        // read through a clone, kept for the original
+        assert!(me.clone().exe().is_some());
+        assert!(me.exe.get().is_some());
+        assert!(me.started().unwrap() <= Local::now());
+        assert_eq!(me.id(), Process::read(me.pid).unwrap().id());
+    }
+}
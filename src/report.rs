@@ -0,0 +1,1032 @@
+use crate::cli::{Filter, Format, Sort};
+use crate::control::DaemonStatus;
+use crate::error::Result;
+use crate::output::{Record, print_csv, print_json};
+use crate::period::{Interval, Period, merge};
+use crate::tracker::HEARTBEAT_SECS;
+use chrono::{DateTime, Local, NaiveDate};
+use rusqlite::{Connection, ToSql, params};
+use serde::Serialize;
+use std::{cmp::Reverse, collections::HashMap};
+
+/// A row of `sessions` together with its game's display name.
+pub struct SessionRow {
+    pub id: i64,
+    pub game: String,
+    pub name: String,
+    pub pid: i32,
+    pub started: DateTime<Local>,
+    pub ended: Option<DateTime<Local>>,
+    pub heartbeat: Option<DateTime<Local>>,
+    pub end_reason: Option<String>,
+    pub segments: Vec<Segment>,
+}
+
+/// A row of `segments`: a stretch of a session spent active, idle, paused
+/// or suspended.
+pub struct Segment {
+    pub kind: String,
+    pub started: DateTime<Local>,
+    /// open for the segment the session is in right now
+    pub ended: Option<DateTime<Local>>,
+}
+
+/// The part of a session inside the selected time, split up.
+#[derive(Default)]
+struct Split {
+    active: Vec<Period>,
+    idle: Vec<Period>,
+}
+
+fn seconds(periods: &[Period]) -> i64 {
+    periods
+        .iter()
+        .map(|(start, end)| (*end - *start).num_seconds())
+        .sum()
+}
+
+impl SessionRow {
+    /// An open session whose daemon has stopped updating it. Recovery will
+    /// close it at its last heartbeat.
+    pub fn is_stale(&self, now: DateTime<Local>) -> bool {
+        self.ended.is_none()
+            && self
+                .heartbeat
+                .is_none_or(|heartbeat| (now - heartbeat).num_seconds() > 2 * HEARTBEAT_SECS)
+    }
+
+    /// When the session ended or, while it is open, how far it has got.
+    pub fn end_at(&self, now: DateTime<Local>) -> DateTime<Local> {
+        match self.ended {
+            Some(ended) => ended,
+            None if self.is_stale(now) => self.heartbeat.unwrap_or(self.started),
+            None => now,
+        }
+    }
+
+    /// Active and idle time inside the filter's window, or `None` if the
+    /// session lies outside it. Paused and suspended time is neither.
+    fn split(&self, filter: &Filter, now: DateTime<Local>) -> Option<Split> {
+        let (start, end) = filter.clip(self.started, self.end_at(now))?;
+        if self.segments.is_empty() {
+            return Some(Split {
+                active: vec![(start, end)],
+                idle: Vec::new(),
+            });
+        }
+        let segments = |kind: &str| -> Vec<Period> {
+            self.segments
+                .iter()
+                .filter(|segment| segment.kind == kind)
+                .filter_map(|segment| {
+                    let from = segment.started.max(start);
+                    let to = segment.ended.unwrap_or(end).min(end);
+                    (from < to).then_some((from, to))
+                })
+                .collect()
+        };
+        Some(Split {
+            active: segments("active"),
+            idle: segments("idle"),
+        })
+    }
+}
+
+impl Filter {
+    /// Start and end of the selected time, where the period and
+    /// `--since`/`--until` narrow each other down.
+    pub fn window(&self) -> (Option<DateTime<Local>>, Option<DateTime<Local>>) {
+        let (period_start, period_end) = self.period.unzip();
+        let start = period_start.max(self.since);
+        let end = match (period_end, self.until) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        (start, end)
+    }
+
+    /// The part of `start..end` inside the window, if any.
+    pub fn clip(
+        &self,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Option<(DateTime<Local>, DateTime<Local>)> {
+        let (since, until) = self.window();
+        let start = since.map_or(start, |since| start.max(since));
+        let end = until.map_or(end, |until| end.min(until));
+        (start < end).then_some((start, end))
+    }
+
+    fn matches(&self, session: &SessionRow) -> bool {
+        let (since, until) = self.window();
+        let overlaps = until.is_none_or(|until| session.started < until)
+            && since.is_none_or(|since| session.ended.is_none_or(|ended| ended > since));
+        overlaps
+            && (self.games.is_empty()
+                || self
+                    .games
+                    .iter()
+                    .any(|game| *game == session.game || *game == session.name))
+    }
+}
+
+/// Narrows `sessions` down to those that may overlap `?1..?2`, either of
+/// which may be NULL. Timestamps are compared as times, as their offsets
+/// differ across DST; `Filter::matches` has the final say.
+const IN_WINDOW: &str = "(?1 IS NULL OR sessions.ended IS NULL
+        OR julianday(sessions.ended) >= julianday(?1))
+    AND (?2 IS NULL OR julianday(sessions.started) < julianday(?2))";
+
+/// Sessions overlapping the filter's window, oldest first.
+pub fn load_sessions(conn: &Connection, filter: &Filter) -> Result<Vec<SessionRow>> {
+    let (since, until) = filter.window();
+    let window = params![since.map(|t| t.to_rfc3339()), until.map(|t| t.to_rfc3339())];
+    let mut stmt = conn.prepare(&format!(
+        "SELECT sessions.id, sessions.game, COALESCE(games.name, sessions.game),
+            sessions.pid, sessions.started, sessions.ended, sessions.heartbeat,
+            sessions.end_reason
+        FROM sessions
+        LEFT JOIN games ON games.id = sessions.game
+        WHERE {}
+        ORDER BY sessions.started, sessions.id",
+        IN_WINDOW
+    ))?;
+    let rows = stmt.query_map(window, |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get::<_, String>(4)?,
+            row.get::<_, Option<String>>(5)?,
+            row.get::<_, Option<String>>(6)?,
+            row.get::<_, Option<String>>(7)?,
+        ))
+    })?;
+
+    let mut sessions = Vec::new();
+    for row in rows {
+        let (id, game, name, pid, started, ended, heartbeat, end_reason) = row?;
+        let Some(started) = parse_timestamp(&started) else {
+            continue;
+        };
+        let session = SessionRow {
+            id,
+            game,
+            name,
+            pid,
+            started,
+            ended: ended.as_deref().and_then(parse_timestamp),
+            heartbeat: heartbeat.as_deref().and_then(parse_timestamp),
+            end_reason,
+            segments: Vec::new(),
+        };
+        if filter.matches(&session) {
+            sessions.push(session);
+        }
+    }
+    load_segments(conn, window, &mut sessions)?;
+    Ok(sessions)
+}
+
+/// Fills in the segments of `sessions`, loaded with the same `window`.
+fn load_segments(
+    conn: &Connection,
+    window: &[&dyn ToSql],
+    sessions: &mut [SessionRow],
+) -> Result<()> {
+    let index: HashMap<i64, usize> = sessions
+        .iter()
+        .enumerate()
+        .map(|(i, session)| (session.id, i))
+        .collect();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT session_id, kind, started, ended FROM segments
+        WHERE session_id IN (SELECT id FROM sessions WHERE {})
+        ORDER BY started",
+        IN_WINDOW
+    ))?;
+    let rows = stmt.query_map(window, |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, Option<String>>(3)?,
+        ))
+    })?;
+    for row in rows {
+        let (session_id, kind, started, ended) = row?;
+        let (Some(&i), Some(started)) = (index.get(&session_id), parse_timestamp(&started)) else {
+            continue;
+        };
+        sessions[i].segments.push(Segment {
+            kind,
+            started,
+            ended: ended.as_deref().and_then(parse_timestamp),
+        });
+    }
+    Ok(())
+}
+
+fn parse_timestamp(s: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(s)
+        .ok()
+        .map(|time| time.with_timezone(&Local))
+}
+
+pub fn format_duration(secs: i64) -> String {
+    let h = secs / 3600;
+    let m = (secs % 3600) / 60;
+    let s = secs % 60;
+    format!("{}h {}m {}s", h, m, s)
+}
+
+#[derive(Serialize)]
+struct Total {
+    game: String,
+    name: String,
+    /// seconds of play, not counting idle time
+    #[serde(rename = "duration")]
+    secs: i64,
+    /// seconds the game ran without input
+    idle: i64,
+    sessions: usize,
+    /// includes a session that is still running
+    in_progress: bool,
+}
+
+impl Record for Total {
+    const FIELDS: &'static [&'static str] = &[
+        "game",
+        "name",
+        "duration",
+        "idle",
+        "sessions",
+        "in_progress",
+    ];
+}
+
+/// Seconds of active play in any of `games`, or any game at all when that
+/// is empty, from each of `since` until `now`. Time spent in two games at
+/// once counts once. The sessions are loaded once, for the earliest.
+pub fn played_since(
+    conn: &Connection,
+    games: &[String],
+    since: &[DateTime<Local>],
+    now: DateTime<Local>,
+) -> Result<Vec<i64>> {
+    let filter = |since| Filter {
+        since,
+        until: Some(now),
+        games: games.to_vec(),
+        ..Filter::default()
+    };
+    let sessions = load_sessions(conn, &filter(since.iter().min().copied()))?;
+    Ok(since
+        .iter()
+        .map(|since| {
+            let filter = filter(Some(*since));
+            let active = sessions
+                .iter()
+                .filter_map(|session| session.split(&filter, now))
+                .flat_map(|split| split.active)
+                .collect();
+            seconds(&merge(active))
+        })
+        .collect())
+}
+
+fn totals(
+    sessions: &[SessionRow],
+    filter: &Filter,
+    sort: Sort,
+    now: DateTime<Local>,
+) -> Vec<Total> {
+    let mut by_game: HashMap<&str, Total> = HashMap::new();
+    for session in sessions {
+        let Some(split) = session.split(filter, now) else {
+            continue;
+        };
+        let total = by_game.entry(&session.game).or_insert_with(|| Total {
+            game: session.game.clone(),
+            name: session.name.clone(),
+            secs: 0,
+            idle: 0,
+            sessions: 0,
+            in_progress: false,
+        });
+        total.secs += seconds(&split.active);
+        total.idle += seconds(&split.idle);
+        total.sessions += 1;
+        total.in_progress |= session.ended.is_none() && !session.is_stale(now);
+    }
+
+    let mut totals: Vec<Total> = by_game.into_values().collect();
+    totals.sort_by(|a, b| a.name.cmp(&b.name));
+    match sort {
+        Sort::Name => {}
+        Sort::Time => totals.sort_by_key(|total| Reverse(total.secs)),
+        Sort::Sessions => totals.sort_by_key(|total| Reverse(total.sessions)),
+    }
+    totals
+}
+
+/// Time per game in each day, week or month of the selected window.
+struct Breakdown {
+    interval: Interval,
+    /// (game id, display name) in column order
+    games: Vec<(String, String)>,
+    /// first day of each bucket, and seconds per game in `games` order
+    rows: Vec<(NaiveDate, Vec<i64>)>,
+}
+
+fn breakdown(
+    sessions: &[SessionRow],
+    filter: &Filter,
+    interval: Interval,
+    sort: Sort,
+    now: DateTime<Local>,
+) -> Breakdown {
+    let mut cells: HashMap<(NaiveDate, &str), i64> = HashMap::new();
+    for session in sessions {
+        let Some(split) = session.split(filter, now) else {
+            continue;
+        };
+        for (started, ended) in split.active {
+            for (bucket, secs) in interval.split(started, ended) {
+                *cells.entry((bucket, &session.game)).or_default() += secs;
+            }
+        }
+    }
+
+    let games: Vec<(String, String)> = totals(sessions, filter, sort, now)
+        .into_iter()
+        .map(|total| (total.game, total.name))
+        .collect();
+    let mut rows = Vec::new();
+    let first = cells.keys().map(|(bucket, _)| *bucket).min();
+    let last = cells.keys().map(|(bucket, _)| *bucket).max();
+    if let (Some(mut bucket), Some(last)) = (first, last) {
+        // empty buckets in between are kept so gaps show up
+        while bucket <= last {
+            let row = games
+                .iter()
+                .map(|(game, _)| cells.get(&(bucket, game.as_str())).copied().unwrap_or(0))
+                .collect();
+            rows.push((bucket, row));
+            bucket = interval.next(bucket);
+        }
+    }
+    Breakdown {
+        interval,
+        games,
+        rows,
+    }
+}
+
+/// One cell of a breakdown. `period` is the first day of the bucket.
+#[derive(Serialize)]
+struct BreakdownRecord<'a> {
+    period: String,
+    label: String,
+    game: &'a str,
+    name: &'a str,
+    duration: i64,
+}
+
+impl Record for BreakdownRecord<'_> {
+    const FIELDS: &'static [&'static str] = &["period", "label", "game", "name", "duration"];
+}
+
+impl Breakdown {
+    /// Non-empty cells, row by row.
+    fn records(&self) -> Vec<BreakdownRecord<'_>> {
+        let mut records = Vec::new();
+        for (bucket, secs) in &self.rows {
+            for ((game, name), secs) in self.games.iter().zip(secs) {
+                if *secs > 0 {
+                    records.push(BreakdownRecord {
+                        period: bucket.to_string(),
+                        label: self.interval.label(*bucket),
+                        game,
+                        name,
+                        duration: *secs,
+                    });
+                }
+            }
+        }
+        records
+    }
+}
+
+/// Hours and minutes, compact enough for table cells.
+fn format_hm(secs: i64) -> String {
+    if secs == 0 {
+        return "-".to_string();
+    }
+    format!("{}:{:02}", secs / 3600, (secs % 3600) / 60)
+}
+
+fn print_breakdown(breakdown: &Breakdown) {
+    let mut table: Vec<Vec<String>> = Vec::new();
+    let mut header = vec![String::new()];
+    header.extend(breakdown.games.iter().map(|(_, name)| name.clone()));
+    header.push("Total".to_string());
+    table.push(header);
+
+    let mut column_totals = vec![0; breakdown.games.len()];
+    for (bucket, secs) in &breakdown.rows {
+        let mut line = vec![breakdown.interval.label(*bucket)];
+        line.extend(secs.iter().map(|secs| format_hm(*secs)));
+        line.push(format_hm(secs.iter().sum()));
+        table.push(line);
+        for (total, secs) in column_totals.iter_mut().zip(secs) {
+            *total += secs;
+        }
+    }
+    let mut footer = vec!["Total".to_string()];
+    footer.extend(column_totals.iter().map(|secs| format_hm(*secs)));
+    footer.push(format_hm(column_totals.iter().sum()));
+    table.push(footer);
+
+    let widths: Vec<usize> = (0..table[0].len())
+        .map(|column| {
+            table
+                .iter()
+                .map(|line| line[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    for line in &table {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(column, (cell, width))| match column {
+                0 => format!("{:<width$}", cell),
+                _ => format!("{:>width$}", cell),
+            })
+            .collect();
+        println!("{}", cells.join("  "));
+    }
+}
+
+pub fn report(
+    conn: &Connection,
+    filter: &Filter,
+    sort: Sort,
+    by: Option<Interval>,
+    format: Format,
+) -> Result<()> {
+    let now = Local::now();
+    let sessions = load_sessions(conn, filter)?;
+    match (by, format) {
+        (Some(interval), Format::Json) => {
+            print_json(&breakdown(&sessions, filter, interval, sort, now).records())
+        }
+        (Some(interval), Format::Csv) => {
+            print_csv(&breakdown(&sessions, filter, interval, sort, now).records())
+        }
+        (None, Format::Json) => print_json(&totals(&sessions, filter, sort, now)),
+        (None, Format::Csv) => print_csv(&totals(&sessions, filter, sort, now)),
+        (by, Format::Table) => {
+            let format = |time: Option<DateTime<Local>>| match time {
+                Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+                None => "...".to_string(),
+            };
+            match filter.window() {
+                (None, None) => println!("Playtime report:"),
+                (since, until) => {
+                    println!("Playtime report, {} to {}:", format(since), format(until))
+                }
+            }
+            if let Some(interval) = by {
+                print_breakdown(&breakdown(&sessions, filter, interval, sort, now));
+                return Ok(());
+            }
+            for total in totals(&sessions, filter, sort, now) {
+                let mut notes = Vec::new();
+                if total.idle > 0 {
+                    notes.push(format!("idle {}", format_duration(total.idle)));
+                }
+                if total.in_progress {
+                    notes.push("in progress".to_string());
+                }
+                let notes = if notes.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", notes.join(", "))
+                };
+                println!("- {}: {}{}", total.name, format_duration(total.secs), notes);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A session as listed by `sessions`. Times are RFC 3339; `duration` (time
+/// played) and `idle` are in seconds and, like `ended` and `end_reason`,
+/// null while the session is open.
+#[derive(Serialize)]
+struct SessionRecord<'a> {
+    id: i64,
+    game: &'a str,
+    name: &'a str,
+    pid: i32,
+    started: String,
+    ended: Option<String>,
+    duration: Option<i64>,
+    idle: Option<i64>,
+    end_reason: Option<&'a str>,
+}
+
+impl Record for SessionRecord<'_> {
+    const FIELDS: &'static [&'static str] = &[
+        "id",
+        "game",
+        "name",
+        "pid",
+        "started",
+        "ended",
+        "duration",
+        "idle",
+        "end_reason",
+    ];
+}
+
+impl SessionRow {
+    /// Seconds played and idle, once the session has ended.
+    pub fn durations(&self) -> Option<(i64, i64)> {
+        let ended = self.ended?;
+        Some(
+            self.split(&Filter::default(), ended)
+                .map_or((0, 0), |split| {
+                    (seconds(&split.active), seconds(&split.idle))
+                }),
+        )
+    }
+
+    fn record(&self) -> SessionRecord<'_> {
+        let (duration, idle) = self.durations().unzip();
+        SessionRecord {
+            id: self.id,
+            game: &self.game,
+            name: &self.name,
+            pid: self.pid,
+            started: self.started.to_rfc3339(),
+            ended: self.ended.map(|ended| ended.to_rfc3339()),
+            duration,
+            idle,
+            end_reason: self.end_reason.as_deref(),
+        }
+    }
+}
+
+pub fn sessions(
+    conn: &Connection,
+    filter: &Filter,
+    limit: Option<usize>,
+    format: Format,
+) -> Result<()> {
+    let sessions = load_sessions(conn, filter)?;
+    let skip = limit.map_or(0, |limit| sessions.len().saturating_sub(limit));
+    let sessions = &sessions[skip..];
+    let records = || sessions.iter().map(SessionRow::record).collect::<Vec<_>>();
+    match format {
+        Format::Json => {
+            print_json(&records());
+            return Ok(());
+        }
+        Format::Csv => {
+            print_csv(&records());
+            return Ok(());
+        }
+        Format::Table => {}
+    }
+    for session in sessions {
+        let ended = match (session.ended, session.durations()) {
+            (Some(ended), Some((played, 0))) => format!(
+                "{} ({})",
+                ended.format("%Y-%m-%d %H:%M:%S"),
+                format_duration(played)
+            ),
+            (Some(ended), Some((played, idle))) => format!(
+                "{} ({}, idle {})",
+                ended.format("%Y-%m-%d %H:%M:%S"),
+                format_duration(played),
+                format_duration(idle)
+            ),
+            _ => "open".to_string(),
+        };
+        println!(
+            "{:>5}  {}  {} - {}  pid {}",
+            session.id,
+            session.name,
+            session.started.format("%Y-%m-%d %H:%M:%S"),
+            ended,
+            session.pid
+        );
+    }
+    Ok(())
+}
+
+pub fn status(conn: &Connection, now: DateTime<Local>) -> Result<()> {
+    let open: Vec<SessionRow> = load_sessions(conn, &Filter::default())?
+        .into_iter()
+        .filter(|session| session.ended.is_none())
+        .collect();
+    if open.is_empty() {
+        println!("Nothing running.");
+        return Ok(());
+    }
+    for session in open {
+        let procs: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM session_processes WHERE session_id = ?1 AND ended IS NULL",
+            [session.id],
+            |row| row.get(0),
+        )?;
+        let state = match session.heartbeat {
+            _ if !session.is_stale(now) => format!("{} processes", procs),
+            Some(heartbeat) => format!(
+                "stale, daemon last seen {}",
+                heartbeat.format("%Y-%m-%d %H:%M")
+            ),
+            None => "stale".to_string(),
+        };
+        print_open(
+            &session.name,
+            session.pid,
+            session.started,
+            session.end_at(now),
+            &state,
+        );
+    }
+    Ok(())
+}
+
+/// Status as reported by the running daemon itself.
+pub fn live_status(status: &DaemonStatus, now: DateTime<Local>) {
+    if status.paused {
+        println!("Tracking is paused (daemon pid {}).", status.pid);
+    } else if status.sessions.is_empty() {
+        println!("Nothing running.");
+    }
+    if !status.paused_games.is_empty() {
+        println!("Paused: {}.", status.paused_games.join(", "));
+    }
+    if let Some(since) = status.idle_since {
+        println!("Idle since {}.", since.format("%Y-%m-%d %H:%M"));
+    }
+    for session in &status.sessions {
+        let paused = if session.paused { ", paused" } else { "" };
+        print_open(
+            &session.name,
+            session.pid,
+            session.started,
+            now,
+            &format!("{} processes{}", session.processes, paused),
+        );
+    }
+}
+
+fn print_open(name: &str, pid: i32, started: DateTime<Local>, until: DateTime<Local>, state: &str) {
+    println!(
+        "- {} (pid {}) since {}: {} ({})",
+        name,
+        pid,
+        started.format("%Y-%m-%d %H:%M"),
+        format_duration((until - started).num_seconds()),
+        state
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::open_in_memory;
+    use crate::period::parse_time;
+    use chrono::Datelike;
+
+    fn insert(conn: &Connection, game: &str, started: &str, ended: Option<&str>) {
+        conn.execute(
+            "INSERT OR IGNORE INTO games (id, name) VALUES (?1, upper(?1))",
+            [game],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO sessions (path, pid, started, ended, game) VALUES (?1, 1, ?2, ?3, ?1)",
+            rusqlite::params![game, started, ended],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_totals() {
+        let conn = open_in_memory();
+        insert(
+            &conn,
+            "a",
+            "2025-06-01T10:00:00+00:00",
+            Some("2025-06-01T11:00:00+00:00"),
+        );
+        insert(
+            &conn,
+            "b",
+            "2025-06-02T10:00:00+00:00",
+            Some("2025-06-02T10:30:00+00:00"),
+        );
+        insert(
+            &conn,
+            "b",
+            "2025-06-03T10:00:00+00:00",
+            Some("2025-06-03T10:30:00+00:00"),
+        );
+        insert(&conn, "b", "2025-06-04T10:00:00+00:00", None);
+
+        let sessions = load_sessions(&conn, &Filter::default()).unwrap();
+        let by_time: Vec<(String, i64)> =
+            totals(&sessions, &Filter::default(), Sort::Time, Local::now())
+                .into_iter()
+                .map(|t| (t.name, t.secs))
+                .collect();
+        assert_eq!(by_time, [("A".to_string(), 3600), ("B".to_string(), 3600)]);
+        let by_sessions = totals(&sessions, &Filter::default(), Sort::Sessions, Local::now());
+        assert_eq!(by_sessions[0].name, "B");
+        assert_eq!(by_sessions[0].sessions, 2);
+
+        let filter = Filter {
+            since: Some(parse_time("2025-06-02T00:00:00+00:00").unwrap()),
+            until: Some(parse_time("2025-06-03T00:00:00+00:00").unwrap()),
+            ..Filter::default()
+        };
+        let ids: Vec<i64> = load_sessions(&conn, &filter)
+            .unwrap()
+            .iter()
+            .map(|s| s.id)
+            .collect();
+        assert_eq!(ids, [2]);
+
+        let filter = Filter {
+            games: vec!["A".to_string()],
+            ..Filter::default()
+        };
+        assert_eq!(load_sessions(&conn, &filter).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_load_only_the_window() {
+        let conn = open_in_memory();
+        // ends 00:30 UTC, written with a summer offset
+        insert(
+            &conn,
+            "a",
+            "2025-06-02T01:00:00+02:00",
+            Some("2025-06-02T02:30:00+02:00"),
+        );
+        insert(&conn, "b", "2025-06-03T10:00:00+00:00", None);
+        let window = |since: &str, until: &str| Filter {
+            since: Some(parse_time(since).unwrap()),
+            until: Some(parse_time(until).unwrap()),
+            ..Filter::default()
+        };
+        let games = |filter| -> Vec<String> {
+            load_sessions(&conn, &filter)
+                .unwrap()
+                .into_iter()
+                .map(|s| s.game)
+                .collect()
+        };
+        assert_eq!(
+            games(window(
+                "2025-06-02T00:00:00+00:00",
+                "2025-06-02T01:00:00+00:00"
+            )),
+            ["a"]
+        );
+        assert!(
+            games(window(
+                "2025-06-02T00:30:00+00:00",
+                "2025-06-03T00:00:00+00:00"
+            ))
+            .is_empty()
+        );
+        // still open, so it reaches up to now
+        assert_eq!(
+            games(window(
+                "2025-06-04T00:00:00+00:00",
+                "2025-06-05T00:00:00+00:00"
+            )),
+            ["b"]
+        );
+    }
+
+    #[test]
+    fn test_clip_to_window() {
+        let conn = open_in_memory();
+        // 23:00 to 01:00 UTC, straddling two days
+        insert(
+            &conn,
+            "a",
+            "2025-06-01T23:00:00+00:00",
+            Some("2025-06-02T01:00:00+00:00"),
+        );
+        let time = |s| Some(parse_time(s).unwrap());
+        let filter = Filter {
+            period: Some((
+                parse_time("2025-06-02T00:00:00+00:00").unwrap(),
+                parse_time("2025-06-03T00:00:00+00:00").unwrap(),
+            )),
+            ..Filter::default()
+        };
+        let sessions = load_sessions(&conn, &filter).unwrap();
+        assert_eq!(
+            totals(&sessions, &filter, Sort::Name, Local::now())[0].secs,
+            3600
+        );
+
+        // --since narrows the period further
+        let filter = Filter {
+            since: time("2025-06-02T00:30:00+00:00"),
+            ..filter
+        };
+        assert_eq!(
+            totals(&sessions, &filter, Sort::Name, Local::now())[0].secs,
+            1800
+        );
+
+        let filter = Filter {
+            until: time("2025-06-01T23:00:00+00:00"),
+            ..Filter::default()
+        };
+        assert!(load_sessions(&conn, &filter).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_breakdown_by_day() {
+        let conn = open_in_memory();
+        let at = |day: u32, hour: u32| {
+            crate::period::start_of_day(NaiveDate::from_ymd_opt(2025, 6, day).unwrap())
+                + chrono::TimeDelta::hours(hour as i64)
+        };
+        let insert_local = |game: &str, start: DateTime<Local>, end: DateTime<Local>| {
+            insert(&conn, game, &start.to_rfc3339(), Some(&end.to_rfc3339()));
+        };
+        // crosses midnight into the 2nd
+        insert_local("a", at(1, 23), at(2, 1));
+        insert_local("b", at(4, 10), at(4, 11));
+
+        let filter = Filter::default();
+        let sessions = load_sessions(&conn, &filter).unwrap();
+        let table = breakdown(&sessions, &filter, Interval::Day, Sort::Name, Local::now());
+        assert_eq!(
+            table.games,
+            [
+                ("a".to_string(), "A".to_string()),
+                ("b".to_string(), "B".to_string())
+            ]
+        );
+        let rows: Vec<(u32, Vec<i64>)> = table
+            .rows
+            .into_iter()
+            .map(|(day, secs)| (day.day(), secs))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (1, vec![3600, 0]),
+                (2, vec![3600, 0]),
+                (3, vec![0, 0]),
+                (4, vec![0, 3600]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_records() {
+        let conn = open_in_memory();
+        insert(
+            &conn,
+            "a",
+            "2025-06-01T10:00:00+00:00",
+            Some("2025-06-01T11:00:00+00:00"),
+        );
+        insert(&conn, "a", "2025-06-02T10:00:00+00:00", None);
+        let sessions = load_sessions(&conn, &Filter::default()).unwrap();
+
+        let json =
+            serde_json::to_value(sessions.iter().map(SessionRow::record).collect::<Vec<_>>())
+                .unwrap();
+        assert_eq!(json[0]["game"], "a");
+        assert_eq!(json[0]["name"], "A");
+        assert_eq!(json[0]["duration"], 3600);
+        assert_eq!(json[1]["ended"], serde_json::Value::Null);
+
+        let totals = serde_json::to_value(totals(
+            &sessions,
+            &Filter::default(),
+            Sort::Name,
+            Local::now(),
+        ))
+        .unwrap();
+        assert_eq!(
+            totals,
+            serde_json::json!([{
+                "game": "a",
+                "name": "A",
+                "duration": 3600,
+                "idle": 0,
+                "sessions": 1,
+                "in_progress": false,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_open_sessions_count_until_now() {
+        let conn = open_in_memory();
+        let now = Local::now();
+        let ago = |mins: i64| (now - chrono::TimeDelta::minutes(mins)).to_rfc3339();
+        insert(&conn, "a", &ago(60), None);
+        insert(&conn, "b", &ago(60), None);
+        conn.execute_batch(&format!(
+            "UPDATE sessions SET heartbeat = '{}' WHERE game = 'a';
+            UPDATE sessions SET heartbeat = '{}' WHERE game = 'b';",
+            ago(0),
+            ago(40)
+        ))
+        .unwrap();
+
+        let sessions = load_sessions(&conn, &Filter::default()).unwrap();
+        let totals: Vec<(String, i64, bool)> =
+            totals(&sessions, &Filter::default(), Sort::Name, now)
+                .into_iter()
+                .map(|t| (t.game, t.secs, t.in_progress))
+                .collect();
+        // b's daemon went away 40 minutes ago
+        assert_eq!(
+            totals,
+            [
+                ("a".to_string(), 3600, true),
+                ("b".to_string(), 1200, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_idle_is_not_played() {
+        let conn = open_in_memory();
+        insert(
+            &conn,
+            "a",
+            "2025-06-01T10:00:00+00:00",
+            Some("2025-06-01T13:00:00+00:00"),
+        );
+        conn.execute_batch(
+            "INSERT INTO segments (session_id, kind, started, ended) VALUES
+                (1, 'active', '2025-06-01T10:00:00+00:00', '2025-06-01T11:00:00+00:00'),
+                (1, 'idle', '2025-06-01T11:00:00+00:00', '2025-06-01T12:00:00+00:00'),
+                (1, 'active', '2025-06-01T12:00:00+00:00', '2025-06-01T12:50:00+00:00'),
+                (1, 'idle', '2025-06-01T12:50:00+00:00', NULL);",
+        )
+        .unwrap();
+        let sessions = load_sessions(&conn, &Filter::default()).unwrap();
+        // an open segment runs to the end of the session
+        assert_eq!(sessions[0].durations(), Some((2 * 3600 - 600, 3600 + 600)));
+
+        let filter = Filter {
+            since: parse_time("2025-06-01T11:45:00+00:00").ok(),
+            ..Filter::default()
+        };
+        let total = &totals(&sessions, &filter, Sort::Name, Local::now())[0];
+        assert_eq!((total.secs, total.idle), (3000, 1500));
+    }
+
+    #[test]
+    fn test_paused_and_suspended_are_not_counted() {
+        let conn = open_in_memory();
+        insert(
+            &conn,
+            "a",
+            "2025-06-01T10:00:00+00:00",
+            Some("2025-06-01T13:00:00+00:00"),
+        );
+        conn.execute_batch(
+            "INSERT INTO segments (session_id, kind, started, ended) VALUES
+                (1, 'active', '2025-06-01T10:00:00+00:00', '2025-06-01T10:20:00+00:00'),
+                (1, 'paused', '2025-06-01T10:20:00+00:00', '2025-06-01T10:30:00+00:00'),
+                (1, 'idle', '2025-06-01T10:30:00+00:00', '2025-06-01T11:00:00+00:00'),
+                (1, 'suspended', '2025-06-01T11:00:00+00:00', '2025-06-01T12:00:00+00:00'),
+                (1, 'active', '2025-06-01T12:00:00+00:00', '2025-06-01T13:00:00+00:00');",
+        )
+        .unwrap();
+        let sessions = load_sessions(&conn, &Filter::default()).unwrap();
+        assert_eq!(sessions[0].durations(), Some((4800, 1800)));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0h 0m 0s");
+        assert_eq!(format_duration(3 * 3600 + 61), "3h 1m 1s");
+    }
+}
@@ -0,0 +1,55 @@
+use crate::control::{Pending, Request};
+use crate::log::info;
+use std::{
+    ptr,
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+/// Turns SIGTERM and SIGINT into a shutdown request and SIGHUP into a
+/// config reload, delivered like requests from the control socket.
+///
+/// The signals are blocked and collected with `sigwait` on a thread of
+/// their own, so this must run before any other thread is spawned: threads
+/// inherit the mask, and one that does not block them would be killed.
+pub fn forward(requests: Sender<Pending>) {
+    // SAFETY: a zeroed sigset_t is filled in by sigemptyset before use, and
+    // every pointer passed is to a local that outlives the call
+    let set = unsafe {
+        let mut set = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+            libc::sigaddset(&mut set, signal);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+        set
+    };
+    thread::spawn(move || {
+        loop {
+            let mut signal = 0;
+            // SAFETY: set and signal are valid for the call
+            if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+                continue;
+            }
+            let request = match signal {
+                libc::SIGHUP => Request::ReloadConfig,
+                _ => Request::Shutdown,
+            };
+            info!("Caught {}", name(signal));
+            let (reply, response) = mpsc::channel();
+            if requests.send((request, reply)).is_err() {
+                break;
+            }
+            let _ = response.recv();
+        }
+    });
+}
+
+fn name(signal: i32) -> &'static str {
+    match signal {
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGINT => "SIGINT",
+        libc::SIGHUP => "SIGHUP",
+        _ => "signal",
+    }
+}
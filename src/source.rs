@@ -0,0 +1,73 @@
+use crate::control::Pending;
+use crate::error::{Error, Result};
+use crate::log::{info, warn};
+use crate::matcher::Matcher;
+use crate::netlink::ProcConnector;
+use crate::procfs::{self, Process, ProcessId};
+use serde::Deserialize;
+use std::{sync::mpsc::Sender, time::Duration};
+
+/// Where the daemon learns which processes are running.
+pub trait ProcessSource {
+    /// Every running process, plus any that exited since the last call
+    /// without having been returned, so short-lived ones are not missed.
+    fn processes(&mut self) -> Result<Vec<Process>>;
+
+    /// Tells an event-driven source what is worth waking the daemon for
+    /// right away: processes matching `rules` and those in open sessions.
+    /// Anything else wakes it at most once per `interval`.
+    fn watch(&mut self, _rules: &[Matcher], _tracked: &[ProcessId], _interval: Duration) {}
+}
+
+/// The `process_source` config setting.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    /// The proc connector if the kernel lets us use it, polling otherwise
+    #[default]
+    Auto,
+    Netlink,
+    Poll,
+}
+
+/// Reads every process under /proc on each call.
+pub struct Polling;
+
+impl ProcessSource for Polling {
+    fn processes(&mut self) -> Result<Vec<Process>> {
+        Ok(procfs::pids()
+            .map_err(Error::Proc)?
+            .into_iter()
+            .filter_map(Process::read)
+            .collect())
+    }
+}
+
+/// Opens the source `kind` asks for. The proc connector needs
+/// CAP_NET_ADMIN; without it the daemon polls instead. `wake` is told
+/// whenever the event-driven source has news.
+pub fn open(kind: SourceKind, wake: Sender<Pending>) -> Box<dyn ProcessSource> {
+    if kind == SourceKind::Poll {
+        return Box::new(Polling);
+    }
+    match ProcConnector::open(wake) {
+        Ok(connector) => {
+            info!("Watching processes through the netlink proc connector");
+            Box::new(connector)
+        }
+        Err(e) if kind == SourceKind::Netlink => {
+            warn!(
+                "Netlink proc connector unavailable ({}), polling /proc instead",
+                e
+            );
+            Box::new(Polling)
+        }
+        Err(e) => {
+            info!(
+                "Polling /proc, as the netlink proc connector is unavailable ({})",
+                e
+            );
+            Box::new(Polling)
+        }
+    }
+}
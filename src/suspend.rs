@@ -0,0 +1,85 @@
+use crate::period::Period;
+use chrono::{DateTime, Local, TimeDelta};
+use std::time::Duration;
+
+/// Shorter differences between the clocks are not worth recording.
+const MIN_SUSPEND: Duration = Duration::from_secs(2);
+
+/// Notices when the machine was suspended or hibernated between two scans.
+pub struct SuspendDetector {
+    /// time asleep since boot, at the last check
+    asleep: Duration,
+    last_check: DateTime<Local>,
+}
+
+impl SuspendDetector {
+    pub fn new(now: DateTime<Local>) -> SuspendDetector {
+        SuspendDetector {
+            asleep: asleep(),
+            last_check: now,
+        }
+    }
+
+    /// The suspend since the last call, if any. Its wall-clock position is
+    /// an estimate: it is taken to start right after the previous check,
+    /// which is at most one poll interval off.
+    pub fn check(&mut self, now: DateTime<Local>) -> Option<Period> {
+        self.check_at(now, asleep())
+    }
+
+    fn check_at(&mut self, now: DateTime<Local>, asleep: Duration) -> Option<Period> {
+        let slept = asleep.saturating_sub(self.asleep);
+        let from = self.last_check;
+        self.asleep = asleep;
+        self.last_check = now;
+        if slept < MIN_SUSPEND {
+            return None;
+        }
+        let to = (from + TimeDelta::from_std(slept).ok()?).min(now);
+        Some((from, to))
+    }
+}
+
+/// CLOCK_BOOTTIME keeps counting while suspended and CLOCK_MONOTONIC does
+/// not, so the difference is the total time spent asleep since boot.
+fn asleep() -> Duration {
+    clock(libc::CLOCK_BOOTTIME).saturating_sub(clock(libc::CLOCK_MONOTONIC))
+}
+
+fn clock(id: libc::clockid_t) -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: ts is a valid timespec to write to
+    unsafe { libc::clock_gettime(id, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let start = Local::now();
+        let at = |secs: i64| start + TimeDelta::seconds(secs);
+        let mut detector = SuspendDetector {
+            asleep: Duration::from_secs(100),
+            last_check: start,
+        };
+        assert_eq!(detector.check_at(at(1), Duration::from_secs(100)), None);
+        // slept an hour between the scans at 1s and 3602s
+        assert_eq!(
+            detector.check_at(at(3602), Duration::from_secs(3700)),
+            Some((at(1), at(3601)))
+        );
+        assert_eq!(detector.check_at(at(3603), Duration::from_secs(3700)), None);
+    }
+
+    #[test]
+    fn test_not_asleep_now() {
+        let mut detector = SuspendDetector::new(Local::now());
+        assert_eq!(detector.check(Local::now()), None);
+    }
+}
@@ -0,0 +1,1130 @@
+use crate::config::{Config, Game};
+use crate::db::write;
+use crate::error::{Error, Result};
+use crate::log::{info, warn};
+use crate::matcher::Matcher;
+use crate::procfs::{Process, ProcessId};
+use chrono::{DateTime, Local};
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// How often open sessions record that the daemon is still watching them.
+/// After a crash they are closed at their last heartbeat.
+pub const HEARTBEAT_SECS: i64 = 15;
+
+/// Which game a process counts towards, and the rule that matched it
+/// directly (`None` for descendants of a matching process).
+type Owner<'a> = (&'a Game, Option<&'a Matcher>);
+
+/// An open session for one game, covering every process that belongs to it.
+struct Session {
+    id: i64,
+    name: String,
+    pid: i32,
+    started: DateTime<Local>,
+    /// process -> row in session_processes
+    procs: HashMap<ProcessId, i64>,
+    /// kind and start of the open segment
+    segment: (SegmentKind, DateTime<Local>),
+}
+
+/// Recorded in `sessions.end_reason`.
+#[derive(Clone, Copy)]
+pub enum EndReason {
+    /// The last process of the game exited
+    ProcessExited,
+    /// The daemon was stopped while the game was running
+    DaemonStopped,
+    /// Found open after the daemon died, closed at its last heartbeat
+    Orphaned,
+    /// The game was taken out of the config
+    GameRemoved,
+    /// Entered by hand with `session add`
+    Manual,
+}
+
+impl EndReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EndReason::ProcessExited => "process_exited",
+            EndReason::DaemonStopped => "daemon_stopped",
+            EndReason::Orphaned => "orphaned",
+            EndReason::GameRemoved => "game_removed",
+            EndReason::Manual => "manual",
+        }
+    }
+}
+
+/// Recorded in `segments.kind`. A session's segments follow each other
+/// without overlap from its start to its end; only active ones count as
+/// played.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SegmentKind {
+    Active,
+    /// No input for longer than the idle threshold
+    Idle,
+    /// Tracking was paused
+    Paused,
+    /// The machine was asleep
+    Suspended,
+}
+
+impl SegmentKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SegmentKind::Active => "active",
+            SegmentKind::Idle => "idle",
+            SegmentKind::Paused => "paused",
+            SegmentKind::Suspended => "suspended",
+        }
+    }
+}
+
+/// What the daemon is tracking right now, as reported over the control
+/// socket.
+#[derive(Serialize, Deserialize)]
+pub struct OpenSession {
+    pub game: String,
+    pub name: String,
+    pub pid: i32,
+    pub started: DateTime<Local>,
+    pub processes: usize,
+    #[serde(default)]
+    pub paused: bool,
+}
+
+/// Turns process scans into sessions. A game's session starts with the first
+/// process belonging to it and ends when the last one exits, however many
+/// helpers it spawns in between.
+#[derive(Default)]
+pub struct Tracker {
+    /// game id -> open session
+    sessions: HashMap<String, Session>,
+    last_heartbeat: Option<DateTime<Local>>,
+    /// last input before the user went idle
+    idle_since: Option<DateTime<Local>>,
+    paused_all: bool,
+    /// ids of games paused on their own
+    paused_games: HashSet<String>,
+}
+
+impl Tracker {
+    /// Picks up sessions a previous daemon left open. Sessions that still
+    /// have a process running (same pid and `starttime` ticks) are adopted,
+    /// the rest are closed at their last heartbeat.
+    pub fn recover(conn: &Connection, lookup: impl Fn(i32) -> Option<Process>) -> Result<Tracker> {
+        let mut tracker = Tracker::default();
+        let mut stmt = conn.prepare(
+            "SELECT sessions.id, sessions.game, COALESCE(games.name, sessions.game),
+                sessions.pid, sessions.started, sessions.heartbeat
+            FROM sessions
+            LEFT JOIN games ON games.id = sessions.game
+            WHERE sessions.ended IS NULL",
+        )?;
+        let open: Vec<(i64, String, String, i32, String, Option<String>)> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let parse = |time: &str| {
+            DateTime::parse_from_rfc3339(time)
+                .ok()
+                .map(|time| time.with_timezone(&Local))
+        };
+        for (id, game_id, name, pid, started, heartbeat) in open {
+            let Some(started) = parse(&started) else {
+                warn!("Leaving session {} open: bad start time {:?}", id, started);
+                continue;
+            };
+            let since = match heartbeat.as_deref().map(|time| (time, parse(time))) {
+                Some((_, Some(heartbeat))) => heartbeat,
+                Some((time, None)) => {
+                    warn!(
+                        "Bad heartbeat {:?} in session {}, taking its start time instead",
+                        time, id
+                    );
+                    started
+                }
+                None => started,
+            };
+            let last_seen = since.to_rfc3339();
+            let mut stmt = conn.prepare(
+                "SELECT id, pid, starttime FROM session_processes
+                WHERE session_id = ?1 AND ended IS NULL",
+            )?;
+            let procs: Vec<(i64, i32, Option<u64>)> = stmt
+                .query_map([id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+
+            let mut session = Session {
+                id,
+                name,
+                pid,
+                started,
+                procs: HashMap::new(),
+                segment: (SegmentKind::Active, since),
+            };
+            let mut gone = Vec::new();
+            for (row, pid, starttime) in procs {
+                // proc_started is only for display: it is rounded and moves
+                // with the clock, the ticks since boot do not
+                let running = lookup(pid).filter(|proc| Some(proc.starttime) == starttime);
+                match running {
+                    Some(proc) => {
+                        session.procs.insert(proc.id(), row);
+                    }
+                    None => gone.push(row),
+                }
+            }
+
+            write(conn, |tx| {
+                // idle and pause are worked out afresh by the new daemon
+                close_segment(tx, id, &last_seen)?;
+                for row in &gone {
+                    end_process(tx, *row, &last_seen)?;
+                }
+                if session.procs.is_empty() {
+                    tx.execute(
+                        "UPDATE sessions SET ended = ?1, end_reason = ?2 WHERE id = ?3",
+                        params![last_seen, EndReason::Orphaned.as_str(), id],
+                    )?;
+                } else {
+                    let (kind, since) = session.segment;
+                    open_segment(tx, id, kind, since)?;
+                }
+                Ok(())
+            })?;
+            if session.procs.is_empty() {
+                info!(
+                    "Closed orphaned session of {} at {}",
+                    session.name, last_seen
+                );
+            } else {
+                info!("Resumed session of {} started before restart", session.name);
+                tracker.sessions.insert(game_id, session);
+            }
+        }
+        Ok(tracker)
+    }
+
+    /// Records what changed since the last scan. If writing to the
+    /// database fails, what was not written is tried again next time.
+    pub fn update(
+        &mut self,
+        conn: &Connection,
+        config: &Config,
+        procs: &[Process],
+        now: DateTime<Local>,
+    ) -> Result<()> {
+        let owners = self.assign(config, procs);
+        let mut seen: HashMap<&str, Vec<(&Process, Option<&Matcher>)>> = HashMap::new();
+        for proc in procs {
+            if let Some(Some((game, rule))) = owners.get(&proc.pid) {
+                seen.entry(game.id.as_str())
+                    .or_default()
+                    .push((proc, *rule));
+            }
+        }
+
+        // detect ended; a session whose processes were all replaced since
+        // the last scan (pid reuse, quick restart) ends here and starts anew
+        let games: Vec<String> = self.sessions.keys().cloned().collect();
+        for game_id in games {
+            let alive: HashSet<ProcessId> = seen
+                .get(game_id.as_str())
+                .map(|members| members.iter().map(|(proc, _)| proc.id()).collect())
+                .unwrap_or_default();
+            let procs = &self.sessions[&game_id].procs;
+            let gone: Vec<ProcessId> = procs
+                .keys()
+                .filter(|id| !alive.contains(id))
+                .copied()
+                .collect();
+            if !procs.is_empty() && gone.len() == procs.len() {
+                self.end(conn, &game_id, now, EndReason::ProcessExited)?;
+            } else if !gone.is_empty() {
+                let session = self.sessions.get_mut(&game_id).unwrap();
+                write(conn, |tx| {
+                    for id in &gone {
+                        end_process(tx, session.procs[id], &now.to_rfc3339())?;
+                    }
+                    Ok(())
+                })?;
+                session.procs.retain(|id, _| !gone.contains(id));
+            }
+        }
+
+        // detect new; a session is written together with its processes, so
+        // it is never left without any when a write fails
+        for game in &config.games {
+            let Some(members) = seen.get(game.id.as_str()) else {
+                continue;
+            };
+            let open = self.sessions.get(&game.id);
+            let mut fresh: Vec<(&Process, Option<&Matcher>, DateTime<Local>)> = Vec::new();
+            for &(proc, rule) in members {
+                if open.is_some_and(|session| session.procs.contains_key(&proc.id())) {
+                    continue;
+                }
+                match proc.started() {
+                    Ok(started) => fresh.push((proc, rule, started)),
+                    Err(e) => warn!(
+                        "Leaving out pid {} ({}): {}",
+                        proc.pid,
+                        proc.comm,
+                        Error::Proc(e)
+                    ),
+                }
+            }
+            if fresh.is_empty() {
+                continue;
+            }
+            let kind = state(self.is_paused(&game.id), self.idle_since);
+            if let Some(session) = self.sessions.get_mut(&game.id) {
+                let rows = write(conn, |tx| add_processes(tx, session.id, &fresh, now))?;
+                session
+                    .procs
+                    .extend(fresh.iter().map(|(proc, ..)| proc.id()).zip(rows));
+                continue;
+            }
+
+            let (first, rule, proc_started) = fresh
+                .iter()
+                .find(|(_, rule, _)| rule.is_some())
+                .unwrap_or(&fresh[0]);
+            let rule = rule.map(|rule| rule.to_string());
+            let (id, rows) = write(conn, |tx| {
+                tx.execute(
+                    "INSERT INTO sessions
+                        (path, pid, proc_started, started, rule, game, heartbeat)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?4)",
+                    params![
+                        first.comm,
+                        first.pid,
+                        proc_started.to_rfc3339(),
+                        now.to_rfc3339(),
+                        rule,
+                        game.id
+                    ],
+                )?;
+                let id = tx.last_insert_rowid();
+                open_segment(tx, id, kind, now)?;
+                Ok((id, add_processes(tx, id, &fresh, now)?))
+            })?;
+            info!(
+                "Started {} (pid {}, {}) at {}",
+                game.name,
+                first.pid,
+                rule.as_deref().unwrap_or("child process"),
+                now
+            );
+            self.sessions.insert(
+                game.id.clone(),
+                Session {
+                    id,
+                    name: game.name.clone(),
+                    pid: first.pid,
+                    started: now,
+                    procs: fresh.iter().map(|(proc, ..)| proc.id()).zip(rows).collect(),
+                    segment: (kind, now),
+                },
+            );
+        }
+
+        if self
+            .last_heartbeat
+            .is_none_or(|last| (now - last).num_seconds() >= HEARTBEAT_SECS)
+        {
+            self.flush(conn, now)?;
+        }
+        // picks up a pause or idle switch whose write failed before
+        self.settle(conn, now)
+    }
+
+    /// Writes a heartbeat for every open session, so readers of the
+    /// database see them as running up to `now`.
+    pub fn flush(&mut self, conn: &Connection, now: DateTime<Local>) -> Result<()> {
+        write(conn, |tx| {
+            for session in self.sessions.values() {
+                tx.execute(
+                    "UPDATE sessions SET heartbeat = ?1 WHERE id = ?2",
+                    params![now.to_rfc3339(), session.id],
+                )?;
+            }
+            Ok(())
+        })?;
+        self.last_heartbeat = Some(now);
+        Ok(())
+    }
+
+    /// Ends every open session, whether or not its processes are still
+    /// running.
+    pub fn end_all(
+        &mut self,
+        conn: &Connection,
+        now: DateTime<Local>,
+        reason: EndReason,
+    ) -> Result<()> {
+        let games: Vec<String> = self.sessions.keys().cloned().collect();
+        for game_id in games {
+            self.end(conn, &game_id, now, reason)?;
+        }
+        Ok(())
+    }
+
+    /// Follows a config reload: sessions of games that are gone end now,
+    /// the rest carry on under their possibly renamed game.
+    pub fn apply_config(
+        &mut self,
+        conn: &Connection,
+        config: &Config,
+        now: DateTime<Local>,
+    ) -> Result<()> {
+        let mut removed = Vec::new();
+        for (game_id, session) in &mut self.sessions {
+            match config.games.iter().find(|game| &game.id == game_id) {
+                Some(game) => session.name = game.name.clone(),
+                None => removed.push(game_id.clone()),
+            }
+        }
+        for game_id in removed {
+            self.end(conn, &game_id, now, EndReason::GameRemoved)?;
+        }
+        Ok(())
+    }
+
+    /// Ends the session of `game_id` along with the processes still in it,
+    /// and forgets it once that is written.
+    fn end(
+        &mut self,
+        conn: &Connection,
+        game_id: &str,
+        now: DateTime<Local>,
+        reason: EndReason,
+    ) -> Result<()> {
+        let session = &self.sessions[game_id];
+        let now_str = now.to_rfc3339();
+        write(conn, |tx| {
+            for row in session.procs.values() {
+                end_process(tx, *row, &now_str)?;
+            }
+            close_segment(tx, session.id, &now_str)?;
+            tx.execute(
+                "UPDATE sessions SET ended = ?1, end_reason = ?2 WHERE id = ?3",
+                params![now_str, reason.as_str(), session.id],
+            )?;
+            Ok(())
+        })?;
+        info!("Ended {} at {}", session.name, now);
+        self.sessions.remove(game_id);
+        Ok(())
+    }
+
+    /// Switches between idle and active. Idle time starts at the last input
+    /// (or when the session started, if later) and ends at the input that
+    /// broke it.
+    pub fn set_idle(
+        &mut self,
+        conn: &Connection,
+        last_input: DateTime<Local>,
+        idle: bool,
+    ) -> Result<()> {
+        if idle != self.idle_since.is_some() {
+            self.idle_since = idle.then_some(last_input);
+        }
+        // also when nothing changed, in case the last switch failed
+        self.settle(conn, last_input)
+    }
+
+    /// Stops or restarts counting play time of one game, or of all of them
+    /// when `game` is `None`. Sessions stay open while paused, and games
+    /// started meanwhile are tracked from the start, but none of it counts
+    /// until resumed. Resuming all also resumes games paused on their own.
+    pub fn set_paused(
+        &mut self,
+        conn: &Connection,
+        game: Option<&str>,
+        paused: bool,
+        now: DateTime<Local>,
+    ) -> Result<()> {
+        match (game, paused) {
+            (Some(game), true) => {
+                self.paused_games.insert(game.to_string());
+            }
+            (Some(game), false) => {
+                self.paused_games.remove(game);
+            }
+            (None, true) => self.paused_all = true,
+            (None, false) => {
+                self.paused_all = false;
+                self.paused_games.clear();
+            }
+        }
+        self.settle(conn, now)
+    }
+
+    /// Records that the machine was asleep from `from` to `to`, for every
+    /// session that was open across it.
+    pub fn record_suspend(
+        &mut self,
+        conn: &Connection,
+        from: DateTime<Local>,
+        to: DateTime<Local>,
+    ) -> Result<()> {
+        for session in self.sessions.values_mut() {
+            let (kind, since) = session.segment;
+            if from.max(since) < to {
+                switch_segment(conn, session, SegmentKind::Suspended, from)?;
+                switch_segment(conn, session, kind, to)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves every session into the segment kind the tracker's state calls
+    /// for, from `at` on.
+    fn settle(&mut self, conn: &Connection, at: DateTime<Local>) -> Result<()> {
+        for (game, session) in self.sessions.iter_mut() {
+            let paused = self.paused_all || self.paused_games.contains(game);
+            let kind = state(paused, self.idle_since);
+            if session.segment.0 != kind {
+                switch_segment(conn, session, kind, at)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether play time of `game` is not being counted.
+    pub fn is_paused(&self, game: &str) -> bool {
+        self.paused_all || self.paused_games.contains(game)
+    }
+
+    /// Whether all games are paused.
+    pub fn is_paused_all(&self) -> bool {
+        self.paused_all
+    }
+
+    /// Ids of the games paused on their own, sorted.
+    pub fn paused_games(&self) -> Vec<String> {
+        let mut games: Vec<String> = self.paused_games.iter().cloned().collect();
+        games.sort();
+        games
+    }
+
+    pub fn idle_since(&self) -> Option<DateTime<Local>> {
+        self.idle_since
+    }
+
+    /// The running processes of `games`, or of every game if that is empty.
+    pub fn processes(&self, games: &[String]) -> Vec<ProcessId> {
+        self.sessions
+            .iter()
+            .filter(|(game, _)| games.is_empty() || games.contains(game))
+            .flat_map(|(_, session)| session.procs.keys().copied())
+            .collect()
+    }
+
+    /// Whether any game is running.
+    pub fn is_tracking(&self) -> bool {
+        !self.sessions.is_empty()
+    }
+
+    pub fn open_sessions(&self) -> Vec<OpenSession> {
+        let mut open: Vec<OpenSession> = self
+            .sessions
+            .iter()
+            .map(|(game, session)| OpenSession {
+                game: game.clone(),
+                name: session.name.clone(),
+                pid: session.pid,
+                started: session.started,
+                processes: session.procs.len(),
+                paused: self.is_paused(game),
+            })
+            .collect();
+        open.sort_by_key(|session| session.started);
+        open
+    }
+
+    /// Works out which game, if any, each process belongs to. A process
+    /// matching a rule belongs to that game; otherwise it inherits from its
+    /// closest owned ancestor, or keeps the session it was already part of
+    /// (helpers stay counted after the launcher that spawned them exits).
+    fn assign<'a>(&self, config: &'a Config, procs: &[Process]) -> HashMap<i32, Option<Owner<'a>>> {
+        let by_pid: HashMap<i32, &Process> = procs.iter().map(|proc| (proc.pid, proc)).collect();
+        let mut owners: HashMap<i32, Option<Owner>> = HashMap::new();
+        for proc in procs {
+            if let Some((game, rule)) = config.find(proc) {
+                owners.insert(proc.pid, Some((game, Some(rule))));
+            }
+        }
+        for game in &config.games {
+            if let Some(session) = self.sessions.get(&game.id) {
+                for id in session.procs.keys() {
+                    if by_pid.get(&id.pid).is_some_and(|proc| proc.id() == *id) {
+                        owners.entry(id.pid).or_insert(Some((game, None)));
+                    }
+                }
+            }
+        }
+
+        for proc in procs {
+            let mut chain = Vec::new();
+            let mut pid = proc.pid;
+            let owner = loop {
+                if let Some(owner) = owners.get(&pid) {
+                    break *owner;
+                }
+                chain.push(pid);
+                match by_pid.get(&pid) {
+                    // a corrupt scan could contain a ppid cycle
+                    Some(proc) if proc.ppid > 0 && chain.len() <= by_pid.len() => pid = proc.ppid,
+                    _ => break None,
+                }
+            };
+            let inherited = owner.map(|(game, _)| (game, None));
+            for pid in chain {
+                owners.insert(pid, inherited);
+            }
+        }
+        owners
+    }
+}
+
+/// Records `procs` as part of the session, returning their rows in order.
+fn add_processes(
+    conn: &Connection,
+    session_id: i64,
+    procs: &[(&Process, Option<&Matcher>, DateTime<Local>)],
+    now: DateTime<Local>,
+) -> rusqlite::Result<Vec<i64>> {
+    let mut rows = Vec::new();
+    for (proc, rule, started) in procs {
+        conn.execute(
+            "INSERT INTO session_processes
+                (session_id, pid, comm, starttime, proc_started, rule, started)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                session_id,
+                proc.pid,
+                proc.comm,
+                proc.starttime,
+                started.to_rfc3339(),
+                rule.map(|rule| rule.to_string()),
+                now.to_rfc3339()
+            ],
+        )?;
+        rows.push(conn.last_insert_rowid());
+    }
+    Ok(rows)
+}
+
+fn end_process(conn: &Connection, row: i64, ended: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE session_processes SET ended = ?1 WHERE id = ?2",
+        params![ended, row],
+    )
+    .map(|_| ())
+}
+
+/// What open sessions are doing given the tracker's state; a pause wins
+/// over idleness.
+fn state(paused: bool, idle_since: Option<DateTime<Local>>) -> SegmentKind {
+    if paused {
+        SegmentKind::Paused
+    } else if idle_since.is_some() {
+        SegmentKind::Idle
+    } else {
+        SegmentKind::Active
+    }
+}
+
+/// Ends the session's open segment at `at` and opens one of `kind` there.
+/// `at` is moved up to the start of the open segment if it lies before it,
+/// so segments never overlap; an open segment that would end up empty
+/// changes kind instead.
+fn switch_segment(
+    conn: &Connection,
+    session: &mut Session,
+    kind: SegmentKind,
+    at: DateTime<Local>,
+) -> Result<()> {
+    let (_, since) = session.segment;
+    let at = at.max(since);
+    write(conn, |tx| {
+        if at == since {
+            tx.execute(
+                "UPDATE segments SET kind = ?1 WHERE session_id = ?2 AND ended IS NULL",
+                params![kind.as_str(), session.id],
+            )?;
+        } else {
+            close_segment(tx, session.id, &at.to_rfc3339())?;
+            open_segment(tx, session.id, kind, at)?;
+        }
+        Ok(())
+    })?;
+    session.segment = (kind, at);
+    Ok(())
+}
+
+fn open_segment(
+    conn: &Connection,
+    session_id: i64,
+    kind: SegmentKind,
+    started: DateTime<Local>,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO segments (session_id, kind, started) VALUES (?1, ?2, ?3)",
+        params![session_id, kind.as_str(), started.to_rfc3339()],
+    )
+    .map(|_| ())
+}
+
+fn close_segment(conn: &Connection, session_id: i64, ended: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE segments SET ended = ?1 WHERE session_id = ?2 AND ended IS NULL",
+        params![ended, session_id],
+    )
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::open_in_memory;
+
+    fn config() -> Config {
+        serde_yaml::from_str(
+            r#"
+games:
+    - id: hollow_knight
+      name: Hollow Knight
+      match: ["hollow_knight"]
+    - id: steam
+      match: ["steam"]
+"#,
+        )
+        .unwrap()
+    }
+
+    fn proc(pid: i32, ppid: i32, comm: &str) -> Process {
+        Process::fake(pid, ppid, comm, None, None)
+    }
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_process_tree_is_one_session() {
+        let conn = open_in_memory();
+        let config = config();
+        let mut tracker = Tracker::default();
+        let now = Local::now();
+
+        // game with a crash handler, plus an unrelated shell
+        let scan = [
+            proc(1, 0, "systemd"),
+            proc(10, 1, "hollow_knight"),
+            proc(11, 10, "crashpad"),
+            proc(12, 1, "bash"),
+        ];
+        tracker.update(&conn, &config, &scan, now).unwrap();
+        // a second instance and a grandchild
+        let scan = [
+            proc(1, 0, "systemd"),
+            proc(10, 1, "hollow_knight"),
+            proc(11, 10, "crashpad"),
+            proc(13, 11, "reporter"),
+            proc(20, 1, "hollow_knight"),
+        ];
+        tracker.update(&conn, &config, &scan, now).unwrap();
+        // main process exits, reparented helper keeps the session open
+        let scan = [proc(1, 0, "systemd"), proc(11, 1, "crashpad")];
+        tracker.update(&conn, &config, &scan, now).unwrap();
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM sessions"), 1);
+        assert_eq!(
+            count(&conn, "SELECT COUNT(*) FROM sessions WHERE ended IS NULL"),
+            1
+        );
+
+        tracker
+            .update(&conn, &config, &[proc(1, 0, "systemd")], now)
+            .unwrap();
+        assert_eq!(
+            count(&conn, "SELECT COUNT(*) FROM sessions WHERE ended IS NULL"),
+            0
+        );
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM session_processes WHERE ended IS NOT NULL"
+            ),
+            4
+        );
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM session_processes WHERE rule IS NULL"
+            ),
+            2
+        );
+    }
+
+    #[test]
+    fn test_recover_orphaned_sessions() {
+        let conn = open_in_memory();
+        let config = config();
+        let start = Local::now();
+
+        let scan = || {
+            let mut game = proc(10, 1, "hollow_knight");
+            game.starttime = 500;
+            let mut launcher = proc(20, 1, "steam");
+            launcher.starttime = 600;
+            [game, launcher]
+        };
+        let mut tracker = Tracker::default();
+        tracker.update(&conn, &config, &scan(), start).unwrap();
+        let later = start + chrono::TimeDelta::seconds(HEARTBEAT_SECS);
+        tracker.update(&conn, &config, &scan(), later).unwrap();
+        // too soon for another heartbeat
+        let last = later + chrono::TimeDelta::seconds(1);
+        tracker.update(&conn, &config, &scan(), last).unwrap();
+        drop(tracker);
+        // the wall clock moved since; only the ticks since boot still match
+        conn.execute(
+            "UPDATE session_processes SET proc_started = '2000-01-01T00:00:00+00:00'",
+            [],
+        )
+        .unwrap();
+
+        // daemon restarts: the game is still running, steam's pid now
+        // belongs to an unrelated process
+        let tracker = Tracker::recover(&conn, |pid| {
+            let mut proc = proc(pid, 1, "whatever");
+            proc.starttime = if pid == 10 { 500 } else { 9000 };
+            Some(proc)
+        })
+        .unwrap();
+        assert_eq!(tracker.sessions.len(), 1);
+        assert!(tracker.sessions.contains_key("hollow_knight"));
+        let steam_ended: String = conn
+            .query_row(
+                "SELECT ended FROM sessions WHERE game = 'steam'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(steam_ended, later.to_rfc3339());
+
+        // nothing running any more: closed at the last heartbeat
+        let tracker = Tracker::recover(&conn, |_| None).unwrap();
+        assert!(tracker.sessions.is_empty());
+        let game_ended: String = conn
+            .query_row(
+                "SELECT ended FROM sessions WHERE game = 'hollow_knight'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(game_ended, later.to_rfc3339());
+    }
+
+    #[test]
+    fn test_recover_bad_heartbeat() {
+        let conn = open_in_memory();
+        let start = Local::now();
+        let mut tracker = Tracker::default();
+        let scan = [proc(10, 1, "hollow_knight")];
+        tracker.update(&conn, &config(), &scan, start).unwrap();
+        drop(tracker);
+        conn.execute("UPDATE sessions SET heartbeat = 'soon'", [])
+            .unwrap();
+
+        // closed at its start, not at the time of the restart
+        Tracker::recover(&conn, |_| None).unwrap();
+        let ended: String = conn
+            .query_row("SELECT ended FROM sessions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(ended, start.to_rfc3339());
+    }
+
+    #[test]
+    fn test_reused_pid_is_new_session() {
+        let conn = open_in_memory();
+        let config = config();
+        let mut tracker = Tracker::default();
+        let now = Local::now();
+
+        for starttime in [100, 100, 250] {
+            let mut game = proc(10, 1, "hollow_knight");
+            game.starttime = starttime;
+            tracker.update(&conn, &config, &[game], now).unwrap();
+        }
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM sessions"), 2);
+        assert_eq!(
+            count(&conn, "SELECT COUNT(*) FROM sessions WHERE ended IS NULL"),
+            1
+        );
+    }
+
+    #[test]
+    fn test_failed_start_is_retried() {
+        let conn = open_in_memory();
+        let config = config();
+        let mut tracker = Tracker::default();
+        let now = Local::now();
+        let scan = [proc(10, 1, "hollow_knight"), proc(11, 10, "crashpad")];
+
+        conn.execute("ALTER TABLE session_processes RENAME TO held", [])
+            .unwrap();
+        assert!(tracker.update(&conn, &config, &scan, now).is_err());
+        assert!(tracker.open_sessions().is_empty());
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM sessions"), 0);
+
+        conn.execute("ALTER TABLE held RENAME TO session_processes", [])
+            .unwrap();
+        let later = now + chrono::TimeDelta::seconds(5);
+        tracker.update(&conn, &config, &scan, later).unwrap();
+        tracker.update(&conn, &config, &scan, later).unwrap();
+        assert_eq!(tracker.open_sessions()[0].processes, 2);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM sessions"), 1);
+    }
+
+    #[test]
+    fn test_end_all() {
+        let conn = open_in_memory();
+        let config = config();
+        let mut tracker = Tracker::default();
+        let now = Local::now();
+
+        let scan = [proc(10, 1, "hollow_knight"), proc(11, 10, "crashpad")];
+        tracker.update(&conn, &config, &scan, now).unwrap();
+        let open = tracker.open_sessions();
+        assert_eq!(open.len(), 1);
+        assert_eq!((open[0].pid, open[0].processes), (10, 2));
+
+        tracker
+            .end_all(&conn, now, EndReason::DaemonStopped)
+            .unwrap();
+        assert!(tracker.open_sessions().is_empty());
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM sessions WHERE end_reason = 'daemon_stopped'"
+            ),
+            1
+        );
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM session_processes WHERE ended IS NULL"
+            ),
+            0
+        );
+        // still running, so the next scan starts a new session
+        tracker.update(&conn, &config, &scan, now).unwrap();
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM sessions"), 2);
+    }
+
+    #[test]
+    fn test_apply_config() {
+        let conn = open_in_memory();
+        let mut tracker = Tracker::default();
+        let now = Local::now();
+        let scan = [proc(10, 1, "hollow_knight"), proc(20, 1, "steam")];
+        tracker.update(&conn, &config(), &scan, now).unwrap();
+
+        let reloaded: Config = serde_yaml::from_str(
+            "games: [{id: hollow_knight, name: HK, match: [hollow_knight.x86_64]}]",
+        )
+        .unwrap();
+        tracker.apply_config(&conn, &reloaded, now).unwrap();
+        let open = tracker.open_sessions();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].name, "HK");
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM sessions WHERE game = 'steam' AND end_reason = 'game_removed'"
+            ),
+            1
+        );
+        // the running process stays in its session under the new rules
+        tracker.update(&conn, &reloaded, &scan, now).unwrap();
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM sessions"), 2);
+        assert_eq!(tracker.open_sessions().len(), 1);
+    }
+
+    /// (game, kind, started, ended) of every segment
+    fn segments(conn: &Connection) -> Vec<(String, String, String, Option<String>)> {
+        let mut stmt = conn
+            .prepare(
+                "SELECT game, kind, segments.started, segments.ended FROM segments
+                JOIN sessions ON sessions.id = session_id ORDER BY game, segments.id",
+            )
+            .unwrap();
+        stmt.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .unwrap()
+        .map(rusqlite::Result::unwrap)
+        .collect()
+    }
+
+    #[test]
+    fn test_segments() {
+        let conn = open_in_memory();
+        let config = config();
+        let mut tracker = Tracker::default();
+        let start = Local::now();
+        let at = |mins: i64| start + chrono::TimeDelta::minutes(mins);
+        let segment = |game: &str, kind: &str, from: i64, to: Option<i64>| {
+            (
+                game.to_string(),
+                kind.to_string(),
+                at(from).to_rfc3339(),
+                to.map(|to| at(to).to_rfc3339()),
+            )
+        };
+
+        tracker
+            .update(&conn, &config, &[proc(10, 1, "hollow_knight")], at(0))
+            .unwrap();
+        // no input since minute 5, noticed at minute 10
+        tracker.set_idle(&conn, at(5), true).unwrap();
+        let both = [proc(10, 1, "hollow_knight"), proc(20, 1, "steam")];
+        tracker.update(&conn, &config, &both, at(12)).unwrap();
+        tracker.set_idle(&conn, at(5), true).unwrap();
+        tracker.set_idle(&conn, at(30), false).unwrap();
+        tracker
+            .update(&conn, &config, &[proc(10, 1, "hollow_knight")], at(40))
+            .unwrap();
+        tracker.set_paused(&conn, None, true, at(50)).unwrap();
+        // a pause wins over idleness
+        tracker.set_idle(&conn, at(55), true).unwrap();
+        tracker.set_paused(&conn, None, false, at(60)).unwrap();
+
+        assert_eq!(
+            segments(&conn),
+            [
+                segment("hollow_knight", "active", 0, Some(5)),
+                segment("hollow_knight", "idle", 5, Some(30)),
+                segment("hollow_knight", "active", 30, Some(50)),
+                segment("hollow_knight", "paused", 50, Some(60)),
+                segment("hollow_knight", "idle", 60, None),
+                // started while idle
+                segment("steam", "idle", 12, Some(30)),
+                segment("steam", "active", 30, Some(40)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pause_one_game() {
+        let conn = open_in_memory();
+        let config = config();
+        let mut tracker = Tracker::default();
+        let start = Local::now();
+        let at = |mins: i64| start + chrono::TimeDelta::minutes(mins);
+        let both = [proc(10, 1, "hollow_knight"), proc(20, 1, "steam")];
+
+        tracker.update(&conn, &config, &both, at(0)).unwrap();
+        tracker
+            .set_paused(&conn, Some("steam"), true, at(10))
+            .unwrap();
+        assert!(tracker.is_paused("steam") && !tracker.is_paused("hollow_knight"));
+        tracker.set_paused(&conn, None, true, at(15)).unwrap();
+        // resuming all resumes steam too
+        tracker.set_paused(&conn, None, false, at(20)).unwrap();
+        assert!(tracker.paused_games().is_empty());
+
+        let kinds: Vec<(String, String)> = segments(&conn)
+            .into_iter()
+            .map(|(game, kind, _, _)| (game, kind))
+            .collect();
+        let expected = [
+            ("hollow_knight", "active"),
+            ("hollow_knight", "paused"),
+            ("hollow_knight", "active"),
+            ("steam", "active"),
+            ("steam", "paused"),
+            ("steam", "active"),
+        ]
+        .map(|(game, kind)| (game.to_string(), kind.to_string()));
+        assert_eq!(kinds, expected);
+    }
+
+    #[test]
+    fn test_record_suspend() {
+        let conn = open_in_memory();
+        let config = config();
+        let mut tracker = Tracker::default();
+        let start = Local::now();
+        let at = |mins: i64| start + chrono::TimeDelta::minutes(mins);
+
+        tracker
+            .update(&conn, &config, &[proc(10, 1, "hollow_knight")], at(0))
+            .unwrap();
+        tracker.set_idle(&conn, at(0), true).unwrap();
+        tracker.record_suspend(&conn, at(-10), at(60)).unwrap();
+        let kinds: Vec<(String, String, Option<String>)> = segments(&conn)
+            .into_iter()
+            .map(|(_, kind, started, ended)| (kind, started, ended))
+            .collect();
+        // clipped to the session, and idle carries on afterwards
+        assert_eq!(
+            kinds,
+            [
+                (
+                    "suspended".to_string(),
+                    at(0).to_rfc3339(),
+                    Some(at(60).to_rfc3339())
+                ),
+                ("idle".to_string(), at(60).to_rfc3339(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_direct_match_beats_ancestor() {
+        let conn = open_in_memory();
+        let config = config();
+        let mut tracker = Tracker::default();
+
+        let scan = [
+            proc(1, 0, "systemd"),
+            proc(10, 1, "steam"),
+            proc(11, 10, "hollow_knight"),
+            proc(12, 11, "renderer"),
+            proc(13, 10, "steamwebhelper"),
+        ];
+        tracker.update(&conn, &config, &scan, Local::now()).unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT game, COUNT(*) FROM session_processes
+                JOIN sessions ON sessions.id = session_id
+                GROUP BY game ORDER BY game",
+            )
+            .unwrap();
+        let games: Vec<(String, i64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(rusqlite::Result::unwrap)
+            .collect();
+        assert_eq!(
+            games,
+            [("hollow_knight".to_string(), 2), ("steam".to_string(), 2)]
+        );
+    }
+}
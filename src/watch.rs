@@ -0,0 +1,154 @@
+use crate::control::{Pending, Request};
+use std::{
+    ffi::{CString, OsStr},
+    io,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+/// Editors save in bursts (truncate, write, rename); events this close
+/// together cause a single reload.
+const SETTLE_MS: i32 = 200;
+
+/// Size of `struct inotify_event` without the name that follows it.
+const EVENT_HEADER: usize = 16;
+
+/// Asks the daemon to reload its config whenever the file at `path` is
+/// written or replaced. The directory is watched rather than the file, so
+/// editors that save by renaming a new file over the old one are noticed.
+pub fn watch_config(path: &Path, requests: Sender<Pending>) -> io::Result<()> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "config path has no parent directory",
+        ));
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    // SAFETY: plain syscall with a constant flag
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let dir = CString::new(dir.as_os_str().as_bytes())?;
+    let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+    // SAFETY: dir is a NUL-terminated string that outlives the call
+    if unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), mask) } < 0 {
+        let e = io::Error::last_os_error();
+        // SAFETY: fd is the inotify instance opened above, not used after this
+        unsafe { libc::close(fd) };
+        return Err(e);
+    }
+
+    let name = name.to_os_string();
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok(events) = read(fd, &mut buf) {
+            if !changed(events, &name) {
+                continue;
+            }
+            let mut pollfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: pollfd is one valid pollfd, as the count says
+            while unsafe { libc::poll(&mut pollfd, 1, SETTLE_MS) } > 0 {
+                if read(fd, &mut buf).is_err() {
+                    break;
+                }
+            }
+            let (reply, response) = mpsc::channel();
+            if requests.send((Request::ReloadConfig, reply)).is_err() {
+                break;
+            }
+            let _ = response.recv();
+        }
+        // SAFETY: fd is ours and not used after this
+        unsafe { libc::close(fd) };
+    });
+    Ok(())
+}
+
+fn read(fd: i32, buf: &mut [u8]) -> io::Result<&[u8]> {
+    loop {
+        // SAFETY: buf is valid for writes of buf.len() bytes
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+        if n >= 0 {
+            return Ok(&buf[..n as usize]);
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Whether a batch of inotify events mentions the file `name`.
+fn changed(mut events: &[u8], name: &OsStr) -> bool {
+    while events.len() >= EVENT_HEADER {
+        let len = u32::from_ne_bytes(events[12..16].try_into().unwrap()) as usize;
+        let Some(event_name) = events.get(EVENT_HEADER..EVENT_HEADER + len) else {
+            break;
+        };
+        // the name is padded with NULs
+        let end = event_name.iter().position(|&b| b == 0).unwrap_or(len);
+        if OsStr::from_bytes(&event_name[..end]) == name {
+            return true;
+        }
+        events = &events[EVENT_HEADER + len..];
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, time::Duration};
+
+    fn event(name: &str) -> Vec<u8> {
+        let mut padded = name.as_bytes().to_vec();
+        padded.resize(name.len().next_multiple_of(16), 0);
+        let mut event = Vec::new();
+        event.extend(1i32.to_ne_bytes());
+        event.extend(libc::IN_CLOSE_WRITE.to_ne_bytes());
+        event.extend(0u32.to_ne_bytes());
+        event.extend((padded.len() as u32).to_ne_bytes());
+        event.extend(padded);
+        event
+    }
+
+    #[test]
+    fn test_changed() {
+        let name = OsStr::new("config.yaml");
+        let mut events = event(".config.yaml.swp");
+        assert!(!changed(&events, name));
+        events.extend(event("config.yaml"));
+        assert!(changed(&events, name));
+        assert!(!changed(&event("config.yaml.bak"), name));
+    }
+
+    #[test]
+    fn test_watch_config() {
+        let dir = std::env::temp_dir().join(format!("playtime-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yaml");
+        let (tx, requests) = mpsc::channel();
+        watch_config(&path, tx).unwrap();
+
+        fs::write(dir.join("other.yaml"), "x").unwrap();
+        // saved by writing a new file and renaming it into place
+        fs::write(dir.join("config.yaml.tmp"), "games: []").unwrap();
+        fs::rename(dir.join("config.yaml.tmp"), &path).unwrap();
+        let (request, _) = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(request, Request::ReloadConfig);
+        // the burst was folded into one reload
+        assert!(requests.recv_timeout(Duration::from_millis(500)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}